@@ -1,6 +1,10 @@
 //! Functions for handling function arguments.
 
-use std::{io::Write, path::PathBuf};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use crate::{config::Config, error_code};
 
@@ -15,11 +19,51 @@ following configuration for generator.type:
 type = { name = "markov_chain", data = "<path to some text file>" }
 or
 type = { name = "static", data = "<path to some file>" }
+or
+type = { name = "link_maze", data = "<URI path that links should point to>" }
+or
+type = { name = "json" }
+or
+[generator.type]
+name = "feed"
+data = { format = "<sitemap, rss or atom>", links = "<URI path that links should point to>" }
+or
+type = { name = "html", data = { text = <random, markov_chain or static generator.type> } }
+or
+type = { name = "secrets", data = "<dotenv, git_config, aws_credentials or private_key>" }
 
 More configuration options are listed in the project README.
 
 USAGE:
   pandoras_pot [FLAGS] [CONFIG]
+  pandoras_pot [FLAGS] check-config [CONFIG]
+  pandoras_pot [FLAGS] sample [SAMPLE OPTIONS] [CONFIG]
+  pandoras_pot [FLAGS] bench [BENCH OPTIONS] [CONFIG]
+
+SUBCOMMANDS:
+  check-config
+    Check the configuration without listening on anything, print every problem with it, and exit
+    with the error code of the first one. Generator data files, the log path and any TLS
+    certificate are checked as well.
+  sample
+    Write what a client would receive from the configured generator to standard output, including
+    the prefix, and exit.
+  bench
+    Stream from the global generator settings and every route in [[routes]] using concurrent
+    in-process clients, report throughput, chunk latency, CPU time and peak memory use for each,
+    and exit. Neither the network nor logging is included.
+
+SAMPLE OPTIONS:
+      --bytes <N>                   Number of bytes to sample
+      --chunks <N>                  Number of chunks to sample, used if --bytes is not given
+                                    [default: 1]
+      --seed <SEED>                 Seed for random data, to get the same output every time
+      --route <PATH>                Sample a route from [[routes]], such as /.env, instead of the
+                                    global generator settings
+
+BENCH OPTIONS:
+      --clients <N>                 Number of clients streaming at the same time [default: 8]
+      --duration <SECONDS>          How long to stream from each generator [default: 10]
 
 ARGS:
   [CONFIG]
@@ -31,12 +75,64 @@ FLAGS:
   -h, --help                        Print help information and exit
   -V, --version                     Print version information and exit
       --print-default-config        Print default configuration and exit
+      --strict                      Refuse to use a configuration with unknown keys, instead of
+                                    only warning about them
 
 AUTHOR:
   Written by Emil Eriksson (github.com/ginger51011)"#;
 
-/// Parses arguments, and an optional provided [`Config`], or an exit code that should be used.
-/// Writes all output to the provided writer.
+/// What to do with the config.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) enum Command {
+    /// Serve generated data.
+    #[default]
+    Serve,
+    /// Check the config for problems, and exit.
+    CheckConfig,
+    /// Write generated data to standard output, and exit.
+    Sample(SampleArgs),
+    /// Measure the throughput of the configured generators, and exit.
+    Bench(BenchArgs),
+}
+
+/// What to sample using the `sample` subcommand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SampleArgs {
+    pub amount: SampleAmount,
+    /// Seed for random data.
+    pub seed: Option<u64>,
+    /// Path of the route in `[[routes]]` to sample, or `None` for the global settings.
+    pub route: Option<String>,
+}
+
+/// How to run the `bench` subcommand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BenchArgs {
+    /// Number of clients streaming at the same time.
+    pub clients: usize,
+    /// How long to stream for each generator.
+    pub duration: Duration,
+}
+
+/// How much data to sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SampleAmount {
+    Bytes(usize),
+    Chunks(usize),
+}
+
+/// What to run, according to the arguments.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Args {
+    pub command: Command,
+    /// The config given as an argument, and the path it was read from.
+    pub config: Option<(Config, PathBuf)>,
+    /// If configs with unknown keys should be rejected.
+    pub strict: bool,
+}
+
+/// Parses arguments, including an optional provided [`Config`] with the path it was read from,
+/// or an exit code that should be used. Writes all output to the provided writer.
 ///
 /// Will print helpful information, so the caller should preferably exit using the provided code
 /// immediately if possible.
@@ -47,12 +143,12 @@ AUTHOR:
 //# use crate::{args::parse_args, config::Config};
 /// // Note: Please check the result of parse_args
 /// let pargs = pico_args::Arguments::from_env();
-/// let config: Config = parse_args(pargs, &mut std::io::stdout()).unwrap();
+/// let args = parse_args(pargs, &mut std::io::stdout()).unwrap();
 /// ```
 pub(crate) fn parse_args<W: Write>(
     mut pargs: pico_args::Arguments,
     output_writer: &mut W,
-) -> Result<Option<Config>, i32> {
+) -> Result<Args, i32> {
     if pargs.contains(["-h", "--help"]) {
         writeln!(output_writer, "{HELP}").map_err(|_| error_code::UNKNOWN_ERROR)?;
         return Err(0);
@@ -66,38 +162,162 @@ pub(crate) fn parse_args<W: Write>(
         return Err(0);
     }
 
+    let strict = pargs.contains("--strict");
+    let options = parse_sample_args(&mut pargs)
+        .and_then(|sample_args| Ok((sample_args, parse_bench_args(&mut pargs)?)));
+    let (sample_args, bench_args) = match options {
+        Ok(options) => options,
+        Err(e) => {
+            writeln!(output_writer, "{e}").map_err(|_| error_code::UNKNOWN_ERROR)?;
+            return Err(error_code::ARGUMENT_ERROR);
+        }
+    };
     let remaining = pargs.finish();
+    let (command, remaining) = match remaining.split_first() {
+        Some((first, rest)) if first == "check-config" => (Command::CheckConfig, rest),
+        Some((first, rest)) if first == "sample" => (
+            Command::Sample(sample_args.clone().unwrap_or_default()),
+            rest,
+        ),
+        Some((first, rest)) if first == "bench" => {
+            (Command::Bench(bench_args.clone().unwrap_or_default()), rest)
+        }
+        _ => (Command::Serve, remaining.as_slice()),
+    };
+
+    // Options of subcommands are not used for anything else
+    let sample_options = sample_args.is_some();
+    let bench_options = bench_args.is_some();
+    let misplaced_options = match command {
+        Command::Sample(_) => bench_options,
+        Command::Bench(_) => sample_options,
+        Command::Serve | Command::CheckConfig => sample_options || bench_options,
+    };
+    if misplaced_options {
+        writeln!(output_writer, "{HELP}").map_err(|_| error_code::UNKNOWN_ERROR)?;
+        return Err(error_code::ARGUMENT_ERROR);
+    }
 
     if remaining.is_empty() {
-        Ok(None)
+        Ok(Args {
+            command,
+            config: None,
+            strict,
+        })
     } else if remaining.len() == 1 {
         let possible_path = &remaining[0];
         let pb = PathBuf::from(possible_path);
-        let c = Config::from_path(&pb);
-        if let Some(actual) = c {
-            Ok(Some(actual))
-        } else {
-            eprintln!(
-                "File at '{}' could not be parsed as proper config",
-                pb.to_string_lossy()
-            );
-            Err(error_code::UNPARSEABLE_CONFIG)
-        }
+        let config = read_config(&pb, strict)?;
+        Ok(Args {
+            command,
+            config: Some((config, pb)),
+            strict,
+        })
     } else {
         writeln!(output_writer, "{HELP}").map_err(|_| error_code::UNKNOWN_ERROR)?;
         Err(error_code::ARGUMENT_ERROR)
     }
 }
 
+impl Default for SampleArgs {
+    fn default() -> Self {
+        Self {
+            amount: SampleAmount::Chunks(1),
+            seed: None,
+            route: None,
+        }
+    }
+}
+
+/// Parses the options of the `sample` subcommand, using defaults for any that are not given, or
+/// returns `None` if none are given.
+fn parse_sample_args(pargs: &mut pico_args::Arguments) -> Result<Option<SampleArgs>, String> {
+    let bytes = pargs
+        .opt_value_from_str("--bytes")
+        .map_err(|e| e.to_string())?;
+    let chunks = pargs
+        .opt_value_from_str("--chunks")
+        .map_err(|e| e.to_string())?;
+    let seed = pargs
+        .opt_value_from_str("--seed")
+        .map_err(|e| e.to_string())?;
+    let route = pargs
+        .opt_value_from_str("--route")
+        .map_err(|e| e.to_string())?;
+    if bytes.is_none() && chunks.is_none() && seed.is_none() && route.is_none() {
+        return Ok(None);
+    }
+    let amount = match (bytes, chunks) {
+        (Some(bytes), None) => SampleAmount::Bytes(bytes),
+        (None, Some(chunks)) => SampleAmount::Chunks(chunks),
+        (None, None) => SampleArgs::default().amount,
+        (Some(_), Some(_)) => return Err("only one of --bytes and --chunks can be given".into()),
+    };
+    Ok(Some(SampleArgs {
+        amount,
+        seed,
+        route,
+    }))
+}
+
+impl Default for BenchArgs {
+    fn default() -> Self {
+        Self {
+            clients: 8,
+            duration: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Parses the options of the `bench` subcommand, using defaults for any that are not given, or
+/// returns `None` if none are given.
+fn parse_bench_args(pargs: &mut pico_args::Arguments) -> Result<Option<BenchArgs>, String> {
+    let clients = pargs
+        .opt_value_from_str("--clients")
+        .map_err(|e| e.to_string())?;
+    if clients == Some(0) {
+        return Err("--clients must be at least 1".into());
+    }
+    let duration: Option<u64> = pargs
+        .opt_value_from_str("--duration")
+        .map_err(|e| e.to_string())?;
+    if clients.is_none() && duration.is_none() {
+        return Ok(None);
+    }
+    let defaults = BenchArgs::default();
+    Ok(Some(BenchArgs {
+        clients: clients.unwrap_or(defaults.clients),
+        duration: duration.map_or(defaults.duration, Duration::from_secs),
+    }))
+}
+
+/// Reads the config at `path`, printing any unknown keys in it.
+///
+/// Returns an exit code if the config cannot be used.
+pub(crate) fn read_config(path: &Path, strict: bool) -> Result<Config, i32> {
+    match Config::from_path(path, strict) {
+        Ok((config, unknown_keys)) => {
+            for key in unknown_keys {
+                eprintln!("Ignoring {key}");
+            }
+            Ok(config)
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            Err(e.exit_code())
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::io::Write;
+    use std::{io::Write, time::Duration};
 
     use tempfile::NamedTempFile;
 
     use crate::{config::Config, error_code};
 
-    use super::{parse_args, HELP, VERSION};
+    use super::{parse_args, Args, BenchArgs, Command, SampleAmount, SampleArgs, HELP, VERSION};
 
     #[test]
     fn no_args_ok() {
@@ -105,13 +325,14 @@ mod tests {
         let mut buf: Vec<u8> = vec![];
         let res = parse_args(pargs, &mut buf);
         assert!(buf.is_empty());
-        match res {
-            Ok(None) => {
-                // Ok
-            }
-            Ok(Some(_)) => panic!("got a config"),
-            Err(_) => panic!("got exit code"),
-        }
+        assert_eq!(
+            res,
+            Ok(Args {
+                command: Command::Serve,
+                config: None,
+                strict: false
+            })
+        );
     }
 
     #[test]
@@ -231,14 +452,163 @@ mod tests {
         let res = parse_args(pargs, &mut buf);
 
         match res {
-            Ok(Some(parsed_config)) => {
+            Ok(Args {
+                command: Command::Serve,
+                config: Some((parsed_config, path)),
+                strict: false,
+            }) => {
                 assert_eq!(
                     parsed_config, written_config,
                     "written and parsed config do not match!"
                 );
+                assert_eq!(path, tmpfile.path());
             }
-            Ok(None) => panic!("did not parse config!"),
+            Ok(_) => panic!("did not parse config!"),
             Err(_) => panic!("got exit code!"),
         }
     }
+
+    #[test]
+    fn strict_rejects_unknown_keys() {
+        let mut tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        tmpfile.write_all(b"[http]\nprot = \"8080\"\n").unwrap();
+
+        let pargs = pico_args::Arguments::from_vec(vec![tmpfile.path().into()]);
+        let res = parse_args(pargs, &mut Vec::new());
+        assert!(matches!(
+            res,
+            Ok(Args {
+                config: Some(_),
+                strict: false,
+                ..
+            })
+        ));
+
+        let pargs = pico_args::Arguments::from_vec(vec!["--strict".into(), tmpfile.path().into()]);
+        let res = parse_args(pargs, &mut Vec::new());
+        assert_eq!(res, Err(error_code::UNKNOWN_CONFIG_KEYS));
+    }
+
+    #[test]
+    fn check_config_subcommand() {
+        let pargs = pico_args::Arguments::from_vec(vec!["check-config".into()]);
+        let res = parse_args(pargs, &mut Vec::new());
+        assert_eq!(
+            res,
+            Ok(Args {
+                command: Command::CheckConfig,
+                config: None,
+                strict: false
+            })
+        );
+
+        let tmpfile = NamedTempFile::new().unwrap();
+        let pargs = pico_args::Arguments::from_vec(vec![
+            "--strict".into(),
+            "check-config".into(),
+            tmpfile.path().into(),
+        ]);
+        let res = parse_args(pargs, &mut Vec::new());
+        assert_eq!(
+            res,
+            Ok(Args {
+                command: Command::CheckConfig,
+                config: Some((Config::default(), tmpfile.path().to_owned())),
+                strict: true
+            })
+        );
+    }
+
+    #[test]
+    fn sample_subcommand() {
+        let pargs = pico_args::Arguments::from_vec(vec![
+            "sample".into(),
+            "--bytes".into(),
+            "100".into(),
+            "--seed".into(),
+            "42".into(),
+            "--route".into(),
+            "/.env".into(),
+        ]);
+        let res = parse_args(pargs, &mut Vec::new());
+        assert_eq!(
+            res.unwrap().command,
+            Command::Sample(SampleArgs {
+                amount: SampleAmount::Bytes(100),
+                seed: Some(42),
+                route: Some("/.env".to_string()),
+            })
+        );
+
+        let pargs = pico_args::Arguments::from_vec(vec!["sample".into()]);
+        let res = parse_args(pargs, &mut Vec::new());
+        assert_eq!(res.unwrap().command, Command::Sample(SampleArgs::default()));
+
+        let pargs = pico_args::Arguments::from_vec(vec![
+            "sample".into(),
+            "--bytes".into(),
+            "1".into(),
+            "--chunks".into(),
+            "1".into(),
+        ]);
+        assert_eq!(
+            parse_args(pargs, &mut Vec::new()),
+            Err(error_code::ARGUMENT_ERROR)
+        );
+
+        // Sample options are only accepted when sampling, even if given their default value
+        for option in [["--seed", "1"], ["--chunks", "1"]] {
+            let pargs = pico_args::Arguments::from_vec(option.map(Into::into).to_vec());
+            assert_eq!(
+                parse_args(pargs, &mut Vec::new()),
+                Err(error_code::ARGUMENT_ERROR)
+            );
+        }
+    }
+
+    #[test]
+    fn bench_subcommand() {
+        let pargs = pico_args::Arguments::from_vec(vec![
+            "bench".into(),
+            "--clients".into(),
+            "64".into(),
+            "--duration".into(),
+            "3".into(),
+        ]);
+        assert_eq!(
+            parse_args(pargs, &mut Vec::new()).unwrap().command,
+            Command::Bench(BenchArgs {
+                clients: 64,
+                duration: Duration::from_secs(3),
+            })
+        );
+
+        let pargs = pico_args::Arguments::from_vec(vec!["bench".into()]);
+        assert_eq!(
+            parse_args(pargs, &mut Vec::new()).unwrap().command,
+            Command::Bench(BenchArgs::default())
+        );
+
+        let pargs =
+            pico_args::Arguments::from_vec(vec!["bench".into(), "--clients".into(), "0".into()]);
+        assert_eq!(
+            parse_args(pargs, &mut Vec::new()),
+            Err(error_code::ARGUMENT_ERROR)
+        );
+
+        // Options of other subcommands are not accepted
+        let pargs =
+            pico_args::Arguments::from_vec(vec!["bench".into(), "--bytes".into(), "1".into()]);
+        assert_eq!(
+            parse_args(pargs, &mut Vec::new()),
+            Err(error_code::ARGUMENT_ERROR)
+        );
+        for option in [["--clients", "8"], ["--duration", "10"]] {
+            let pargs = pico_args::Arguments::from_vec(option.map(Into::into).to_vec());
+            assert_eq!(
+                parse_args(pargs, &mut Vec::new()),
+                Err(error_code::ARGUMENT_ERROR)
+            );
+        }
+    }
 }
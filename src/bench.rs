@@ -0,0 +1,288 @@
+//! A throughput benchmark of the configured generators, to pick settings such as
+//! `generator.chunk_size` and `generator.chunk_buffer` for the hardware they run on.
+
+use std::{fmt, io::Write, iter, time::Duration};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::on,
+    Router,
+};
+use tokio::{task::JoinSet, time::Instant};
+use tokio_stream::StreamExt;
+use tower::ServiceExt;
+
+use crate::{
+    add_layers, args::BenchArgs, check, config::Config, error_code, generator::GeneratorStrategy,
+    request_context::RequestContext, text_stream, RouteState, Routes, ANY_METHOD,
+};
+
+/// How long clients wait before trying again when refused, so they do not spin.
+const RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Benchmarks the global generator settings and every route in `[[routes]]`, one at a time,
+/// writing the results to `output`.
+///
+/// Returns an exit code if the config cannot be used, or the results cannot be written.
+pub(crate) async fn bench<W: Write>(
+    config: &Config,
+    args: &BenchArgs,
+    output: &mut W,
+) -> Result<(), i32> {
+    check::report(&check::check(config))?;
+    let routes = Routes::new(config)?;
+
+    let targets = iter::once(("global settings".to_string(), &routes.default)).chain(
+        routes
+            .configured
+            .iter()
+            .map(|(path, route)| (format!("route '{path}'"), route)),
+    );
+    for (name, route) in targets {
+        writeln!(
+            output,
+            "Benchmarking {} generator ({name}) with {} clients for {} s",
+            route.generator_strategy.name(),
+            args.clients,
+            args.duration.as_secs_f64(),
+        )
+        .and_then(|()| output.flush())
+        .map_err(write_failed)?;
+
+        let results = run(config, route, args).await;
+        write!(output, "{results}").map_err(write_failed)?;
+    }
+    Ok(())
+}
+
+fn write_failed(e: std::io::Error) -> i32 {
+    eprintln!("failed to write benchmark results due to error:\n\t{e}");
+    error_code::UNKNOWN_ERROR
+}
+
+/// Streams from `route` using `args.clients` clients for `args.duration`.
+async fn run(config: &Config, route: &RouteState, args: &BenchArgs) -> Results {
+    // Served like any other route, with the same layers
+    let route = route.clone();
+    let app = add_layers(
+        Router::new().fallback(on(ANY_METHOD, move |context: RequestContext| {
+            text_stream(route, context)
+        })),
+        config,
+    );
+
+    let usage_before = resource_usage();
+    let start = Instant::now();
+    let deadline = start + args.duration;
+    let mut clients = JoinSet::new();
+    for _ in 0..args.clients {
+        clients.spawn(client(app.clone(), deadline));
+    }
+    let clients = clients.join_all().await;
+    let elapsed = start.elapsed();
+    let usage_after = resource_usage();
+
+    let cpu_time = usage_before
+        .zip(usage_after)
+        .map(|((before, _), (after, _))| after.saturating_sub(before));
+    Results {
+        elapsed,
+        clients,
+        cpu_time,
+        peak_rss: usage_after.map(|(_, peak_rss)| peak_rss),
+    }
+}
+
+/// What a single client received during a benchmark.
+#[derive(Debug, Default)]
+struct ClientStats {
+    bytes: usize,
+    streams: usize,
+    refused: usize,
+    /// Time between each chunk, counting the first one from when the request was made.
+    latencies: Vec<Duration>,
+}
+
+/// Keeps streaming from `app` until `deadline`, starting a new stream whenever one ends.
+async fn client(app: Router, deadline: Instant) -> ClientStats {
+    let mut stats = ClientStats::default();
+    while Instant::now() < deadline {
+        let mut last_chunk = Instant::now();
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        if response.status() != StatusCode::OK {
+            stats.refused += 1;
+            tokio::time::sleep_until(deadline.min(Instant::now() + RETRY_DELAY)).await;
+            continue;
+        }
+
+        stats.streams += 1;
+        let mut body = response.into_body().into_data_stream();
+        while let Ok(Some(Ok(chunk))) = tokio::time::timeout_at(deadline, body.next()).await {
+            let now = Instant::now();
+            stats.latencies.push(now - last_chunk);
+            last_chunk = now;
+            stats.bytes += chunk.len();
+        }
+    }
+    stats
+}
+
+/// Results of benchmarking a single route.
+#[derive(Debug)]
+struct Results {
+    elapsed: Duration,
+    clients: Vec<ClientStats>,
+    /// CPU time used by the whole process while benchmarking, if known.
+    cpu_time: Option<Duration>,
+    /// Peak resident set size of the process so far in bytes, if known.
+    peak_rss: Option<u64>,
+}
+
+impl Results {
+    /// Throughput in MB/s of `bytes` sent during the benchmark.
+    fn throughput(&self, bytes: usize) -> f64 {
+        (bytes as f64) * 1e-6 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for Results {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total_bytes = self.clients.iter().map(|c| c.bytes).sum();
+        let per_client = self.clients.iter().map(|c| self.throughput(c.bytes));
+        writeln!(
+            f,
+            "  Throughput:    {:.2} MB/s in total, {:.2} MB/s per client (min {:.2}, max {:.2})",
+            self.throughput(total_bytes),
+            self.throughput(total_bytes) / self.clients.len() as f64,
+            per_client.clone().fold(f64::INFINITY, f64::min),
+            per_client.fold(0.0, f64::max),
+        )?;
+        writeln!(
+            f,
+            "  Streams:       {} started, {} refused",
+            self.clients.iter().map(|c| c.streams).sum::<usize>(),
+            self.clients.iter().map(|c| c.refused).sum::<usize>(),
+        )?;
+
+        let mut latencies: Vec<Duration> = self
+            .clients
+            .iter()
+            .flat_map(|c| c.latencies.iter().copied())
+            .collect();
+        latencies.sort_unstable();
+        if latencies.is_empty() {
+            writeln!(f, "  Chunk latency: no chunks received")?;
+        } else {
+            let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+            writeln!(
+                f,
+                "  Chunk latency: p50 {:.1?}, p90 {:.1?}, p99 {:.1?}, max {:.1?}",
+                percentile(0.5),
+                percentile(0.9),
+                percentile(0.99),
+                latencies[latencies.len() - 1],
+            )?;
+        }
+
+        match self.cpu_time {
+            Some(cpu_time) => writeln!(
+                f,
+                "  CPU time:      {:.2} s ({:.0}% of one core)",
+                cpu_time.as_secs_f64(),
+                cpu_time.as_secs_f64() / self.elapsed.as_secs_f64() * 100.0,
+            )?,
+            None => writeln!(f, "  CPU time:      unknown")?,
+        }
+        match self.peak_rss {
+            Some(peak_rss) => writeln!(f, "  Peak RSS:      {:.1} MB", (peak_rss as f64) * 1e-6),
+            None => writeln!(f, "  Peak RSS:      unknown"),
+        }
+    }
+}
+
+/// CPU time used by this process so far, and its peak resident set size in bytes.
+#[cfg(unix)]
+fn resource_usage() -> Option<(Duration, u64)> {
+    use nix::sys::{
+        resource::{getrusage, UsageWho},
+        time::TimeValLike,
+    };
+
+    let usage = getrusage(UsageWho::RUSAGE_SELF).ok()?;
+    let cpu_time = usage.user_time() + usage.system_time();
+    let cpu_time = Duration::from_micros(cpu_time.num_microseconds().try_into().ok()?);
+    let max_rss = u64::try_from(usage.max_rss()).ok()?;
+    // In bytes on macOS, but kilobytes everywhere else
+    let peak_rss = if cfg!(target_os = "macos") {
+        max_rss
+    } else {
+        max_rss * 1024
+    };
+    Some((cpu_time, peak_rss))
+}
+
+#[cfg(not(unix))]
+fn resource_usage() -> Option<(Duration, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{args::BenchArgs, config::Config, Routes};
+
+    use super::{bench, run};
+
+    #[tokio::test]
+    async fn measures_throughput() {
+        let config = Config::default();
+        let routes = Routes::new(&config).unwrap();
+        let args = BenchArgs {
+            clients: 2,
+            duration: Duration::from_millis(200),
+        };
+        let results = run(&config, &routes.default, &args).await;
+
+        assert_eq!(results.clients.len(), 2);
+        for client in &results.clients {
+            assert_eq!(client.streams, 1);
+            assert_eq!(client.refused, 0);
+            assert!(client.bytes > 0);
+            assert!(!client.latencies.is_empty());
+        }
+        assert!(results.elapsed >= args.duration);
+        #[cfg(unix)]
+        assert!(results.peak_rss.unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn benchmarks_every_route() {
+        let toml_str = r#"
+            [generator]
+            max_concurrent = 1
+            overflow_policy = { name = "service_unavailable", data = 60 }
+
+            [[routes]]
+            path = "/.env"
+            type = { name = "link_maze", data = "/maze" }
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let args = BenchArgs {
+            clients: 2,
+            duration: Duration::from_millis(100),
+        };
+        let mut output = Vec::new();
+        bench(&config, &args, &mut output).await.unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("random generator (global settings)"));
+        assert!(output.contains("link_maze generator (route '/.env')"));
+        assert_eq!(output.matches("Throughput:").count(), 2);
+        // Only one of the clients gets a generator, but both routes have their own
+        assert!(output.contains("1 started"));
+        assert!(!output.contains(" 0 refused"));
+    }
+}
@@ -0,0 +1,589 @@
+//! Checks of a whole config, so that every problem with it can be reported at once, before
+//! anything is served.
+
+use std::{collections::HashSet, fmt, fs, io, iter, path::Path};
+
+use axum::http::HeaderValue;
+
+use crate::{
+    config::{Config, GeneratorConfig, GeneratorType, ListenerConfig, OverflowPolicy, TextSource},
+    error_code,
+    generator::{
+        compression_bomb_strategy::CompressionBomb, markov_strategy::read_chain, P_TAG_SIZE,
+    },
+    resolve_listeners,
+};
+
+/// Something that stops a config from being used, with the code `pandoras_pot` exits with
+/// because of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Problem {
+    pub code: i32,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Prints `problems`, and returns the exit code of the first one if there are any.
+pub(crate) fn report(problems: &[Problem]) -> Result<(), i32> {
+    for problem in problems {
+        eprintln!("{problem}");
+    }
+    problems.first().map_or(Ok(()), |problem| Err(problem.code))
+}
+
+/// Finds every problem that stops `config` from being served, without binding any addresses.
+/// Generator data files are read, but the log file, TLS certificate, Markov chains and
+/// compression bombs are only checked by [`check_startup`].
+pub(crate) fn check(config: &Config) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    check_generator(config, &mut problems);
+    check_http(config, &mut problems);
+    check_routes(config, &mut problems);
+    problems
+}
+
+/// Finds problems with what is only used when starting, which is the log file and the TLS
+/// certificate, and with what takes a while to create, which is Markov chains and compression
+/// bombs. Those are created once more when starting, so this is only worth it when checking a
+/// config without starting.
+pub(crate) fn check_startup(config: &Config) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    if let Err(e) = check_log_path(Path::new(&config.logging.output_path)) {
+        problems.push(Problem::new(
+            error_code::CANNOT_OPEN_LOG_FILE,
+            format!(
+                "failed to open log path '{}' due to error:\n\t{e}",
+                config.logging.output_path
+            ),
+        ));
+    }
+
+    #[cfg(feature = "tls")]
+    if let Some(Err(e)) = config.http.tls.as_ref().map(crate::tls::check) {
+        problems.push(Problem::new(error_code::CANNOT_LOAD_TLS_CERTIFICATE, e));
+    }
+
+    let route_types = config.routes.iter().enumerate().filter_map(|(i, route)| {
+        Some((format!("routes[{i}].type"), route.generator_type.as_ref()?))
+    });
+    for (key, generator_type) in iter::once((
+        "generator.type".to_string(),
+        &config.generator.generator_type,
+    ))
+    .chain(route_types)
+    {
+        let markov_chain = match generator_type {
+            GeneratorType::MarkovChain(path) => Some((key, path)),
+            GeneratorType::Feed(feed) => feed
+                .markov_chain
+                .as_ref()
+                .map(|path| (format!("{key}.data.markov_chain"), path)),
+            GeneratorType::Html(html) => match &html.text {
+                TextSource::MarkovChain(path) => Some((format!("{key}.data.text"), path)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some((key, path)) = markov_chain else {
+            continue;
+        };
+        if let Err(e) = read_chain(path) {
+            problems.push(Problem::new(
+                error_code::CANNOT_READ_GENERATOR_DATA_FILE,
+                format!("{key} {e}"),
+            ));
+        }
+    }
+
+    let route_configs = config.routes.iter().enumerate().map(|(i, route)| {
+        (
+            format!("routes[{i}]"),
+            route.generator_config(&config.generator),
+        )
+    });
+    for (key, generator_config) in
+        iter::once(("generator".to_string(), config.generator.clone())).chain(route_configs)
+    {
+        if generator_config.compression_bomb.is_empty() {
+            continue;
+        }
+        if let Err(e) = CompressionBomb::new(
+            &generator_config.compression_bomb,
+            &generator_config.prefix,
+            generator_config.chunk_size,
+        ) {
+            problems.push(Problem::new(
+                error_code::CANNOT_CREATE_COMPRESSION_BOMB,
+                format!("{key}.compression_bomb {e}"),
+            ));
+        }
+    }
+
+    problems
+}
+
+fn check_generator(config: &Config, problems: &mut Vec<Problem>) {
+    // This will mess upp for example markov
+    if config.generator.chunk_size < P_TAG_SIZE {
+        problems.push(Problem::new(
+            error_code::GENERATOR_CHUNK_SIZE_TOO_SMALL,
+            format!(
+                "generator.chunk_size too small \
+                 (min size is {P_TAG_SIZE}, but it should be bigger!)"
+            ),
+        ));
+    }
+
+    if config.generator.chunk_buffer < 1 {
+        problems.push(Problem::new(
+            error_code::GENERATOR_CHUNK_BUFFER_TOO_SMALL,
+            "generator.chunk_buffer must be >= 1",
+        ));
+    }
+
+    check_generator_type("generator.type", &config.generator.generator_type, problems);
+    // The default prefix is meant for HTML, so only one that was set on purpose is a mistake
+    let prefix = &config.generator.prefix;
+    check_prefix_and_suffix(
+        "generator",
+        &config.generator.generator_type,
+        (*prefix != GeneratorConfig::default().prefix).then_some(prefix),
+        Some(&config.generator.suffix),
+        problems,
+    );
+    check_overflow_policy(
+        "generator.overflow_policy",
+        &config.generator.overflow_policy,
+        problems,
+    );
+}
+
+fn check_http(config: &Config, problems: &mut Vec<Problem>) {
+    let http = &config.http;
+
+    // u64, so not below zero
+    if http.rate_limit != 0 && http.rate_limit_period == 0 {
+        problems.push(Problem::new(
+            error_code::BAD_CONFIG,
+            "You cannot activate rate limiting and then set the period to 0!",
+        ));
+    }
+
+    let client_limits = &http.client_limits;
+    if client_limits.rate_limit != 0 && client_limits.rate_limit_period == 0 {
+        problems.push(Problem::new(
+            error_code::BAD_CONFIG,
+            "You cannot activate client rate limiting and then set the period to 0!",
+        ));
+    }
+    if client_limits.max_clients == 0 {
+        problems.push(Problem::new(
+            error_code::BAD_CONFIG,
+            "http.client_limits.max_clients must be >= 1",
+        ));
+    }
+
+    check_content_type("http.content_type", &http.content_type, problems);
+
+    #[cfg(not(feature = "tls"))]
+    if http.tls.is_some() {
+        problems.push(Problem::new(
+            error_code::TLS_NOT_SUPPORTED,
+            "http.tls is set, but pandoras_pot was built without the 'tls' feature!",
+        ));
+    }
+
+    match resolve_listeners(http) {
+        Ok((listeners, health_listeners)) => {
+            check_listeners(config, &listeners, &health_listeners, problems);
+        }
+        Err(problem) => problems.push(problem),
+    }
+}
+
+fn check_listeners(
+    config: &Config,
+    listeners: &[ListenerConfig],
+    health_listeners: &[ListenerConfig],
+    problems: &mut Vec<Problem>,
+) {
+    let mut addresses = HashSet::with_capacity(listeners.len() + health_listeners.len());
+    for listener in listeners.iter().chain(health_listeners) {
+        if !addresses.insert(&listener.address) {
+            problems.push(Problem::new(
+                error_code::BAD_CONFIG,
+                format!("Cannot listen on {} more than once!", listener.address),
+            ));
+        }
+    }
+
+    for listener in listeners {
+        let Some(routes) = &listener.routes else {
+            continue;
+        };
+        if routes.is_empty() {
+            problems.push(Problem::new(
+                error_code::BAD_CONFIG,
+                format!("Listener on {} has no routes!", listener.address),
+            ));
+        }
+        let mut seen = HashSet::with_capacity(routes.len());
+        for path in routes {
+            if !seen.insert(path) {
+                problems.push(Problem::new(
+                    error_code::BAD_CONFIG,
+                    format!("route '{path}' was given more than once for the same listener!"),
+                ));
+            }
+        }
+    }
+
+    for listener in health_listeners.iter().filter(|l| l.routes.is_some()) {
+        problems.push(Problem::new(
+            error_code::BAD_CONFIG,
+            format!(
+                "Health check listener on {} cannot have routes!",
+                listener.address
+            ),
+        ));
+    }
+
+    // Listeners with routes of their own do not use the catch-all
+    let http = &config.http;
+    if !http.catch_all
+        && http.routes.is_empty()
+        && config.routes.is_empty()
+        && listeners.iter().any(|l| l.routes.is_none())
+    {
+        problems.push(Problem::new(
+            error_code::BAD_CONFIG,
+            "http.catch_all was disabled, but no routes was provided!",
+        ));
+    }
+}
+
+fn check_routes(config: &Config, problems: &mut Vec<Problem>) {
+    let mut route_paths = HashSet::with_capacity(config.routes.len());
+    for (i, route) in config.routes.iter().enumerate() {
+        if !route_paths.insert(route.path.as_str()) {
+            problems.push(Problem::new(
+                error_code::BAD_CONFIG,
+                format!("route '{}' was configured more than once!", route.path),
+            ));
+        }
+
+        // Anything not overridden has been checked already
+        if let Some(generator_type) = &route.generator_type {
+            check_generator_type(&format!("routes[{i}].type"), generator_type, problems);
+        }
+        check_prefix_and_suffix(
+            &format!("routes[{i}]"),
+            route
+                .generator_type
+                .as_ref()
+                .unwrap_or(&config.generator.generator_type),
+            route.prefix.as_ref(),
+            route.suffix.as_ref(),
+            problems,
+        );
+        if let Some(content_type) = &route.content_type {
+            check_content_type(&format!("routes[{i}].content_type"), content_type, problems);
+        }
+        if let Some(overflow_policy) = &route.overflow_policy {
+            check_overflow_policy(
+                &format!("routes[{i}].overflow_policy"),
+                overflow_policy,
+                problems,
+            );
+        }
+    }
+}
+
+fn check_generator_type(key: &str, generator_type: &GeneratorType, problems: &mut Vec<Problem>) {
+    match generator_type {
+        GeneratorType::MarkovChain(path) | GeneratorType::Static(path) => {
+            check_data_file(key, path, problems);
+        }
+        GeneratorType::Feed(feed) => {
+            if let Some(path) = &feed.markov_chain {
+                check_data_file(&format!("{key}.data.markov_chain"), path, problems);
+            }
+        }
+        GeneratorType::Html(html) => match &html.text {
+            TextSource::MarkovChain(path) | TextSource::Static(path) => {
+                check_data_file(&format!("{key}.data.text"), path, problems);
+            }
+            TextSource::Random => {}
+        },
+        GeneratorType::Random
+        | GeneratorType::LinkMaze(_)
+        | GeneratorType::Json
+        | GeneratorType::Secrets(_) => {}
+    }
+}
+
+/// Reports a non-empty `prefix` or `suffix` set for a generator type that starts and ends its
+/// output itself, and so never uses them.
+fn check_prefix_and_suffix(
+    key: &str,
+    generator_type: &GeneratorType,
+    prefix: Option<&String>,
+    suffix: Option<&String>,
+    problems: &mut Vec<Problem>,
+) {
+    if !matches!(
+        generator_type,
+        GeneratorType::Json | GeneratorType::Feed(_) | GeneratorType::Secrets(_)
+    ) {
+        return;
+    }
+    for (name, value) in [("prefix", prefix), ("suffix", suffix)] {
+        if value.is_some_and(|value| !value.is_empty()) {
+            problems.push(Problem::new(
+                error_code::BAD_CONFIG,
+                format!("{key}.{name} is not used by the {generator_type}, so it must be \"\""),
+            ));
+        }
+    }
+}
+
+fn check_overflow_policy(key: &str, policy: &OverflowPolicy, problems: &mut Vec<Problem>) {
+    match policy {
+        OverflowPolicy::Tarpit(0) => problems.push(Problem::new(
+            error_code::BAD_CONFIG,
+            format!("{key} cannot tarpit at 0 bytes per second"),
+        )),
+        OverflowPolicy::Static(path) => check_data_file(key, path, problems),
+        _ => {}
+    }
+}
+
+fn check_data_file(key: &str, path: &Path, problems: &mut Vec<Problem>) {
+    if let Err(e) = fs::read_to_string(path) {
+        problems.push(Problem::new(
+            error_code::CANNOT_READ_GENERATOR_DATA_FILE,
+            format!(
+                "{key} cannot read '{}' due to error:\n\t{e}",
+                path.to_string_lossy()
+            ),
+        ));
+    }
+}
+
+fn check_content_type(key: &str, content_type: &str, problems: &mut Vec<Problem>) {
+    if let Err(e) = content_type.parse::<HeaderValue>() {
+        problems.push(Problem::new(
+            error_code::BAD_CONTENT_TYPE,
+            format!("cannot parse {key} '{content_type}' to valid header due to error: {e}"),
+        ));
+    }
+}
+
+/// Checks that logs can be appended to `path`, without leaving a file behind if there is none.
+fn check_log_path(path: &Path) -> io::Result<()> {
+    if path.exists() {
+        fs::OpenOptions::new().append(true).open(path).map(drop)
+    } else {
+        fs::File::create_new(path)?;
+        fs::remove_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use crate::{config::Config, error_code};
+
+    use super::{check, check_startup, report};
+
+    fn codes(problems: &[super::Problem]) -> Vec<i32> {
+        problems.iter().map(|p| p.code).collect()
+    }
+
+    #[test]
+    fn default_config_has_no_problems() {
+        assert_eq!(check(&Config::default()), vec![]);
+        assert_eq!(report(&[]), Ok(()));
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let toml_str = r#"
+            [generator]
+            chunk_size = 1
+            chunk_buffer = 0
+            type = { name = "markov_chain", data = "/does/not/exist" }
+
+            [http]
+            content_type = "text/html\n"
+            rate_limit = 10
+            rate_limit_period = 0
+            catch_all = false
+            routes = []
+
+            [[routes]]
+            path = "/.env"
+            overflow_policy = { name = "tarpit", data = 0 }
+
+            [[routes]]
+            path = "/.env"
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let problems = check(&config);
+        assert_eq!(
+            codes(&problems),
+            [
+                error_code::GENERATOR_CHUNK_SIZE_TOO_SMALL,
+                error_code::GENERATOR_CHUNK_BUFFER_TOO_SMALL,
+                error_code::CANNOT_READ_GENERATOR_DATA_FILE,
+                error_code::BAD_CONFIG,
+                error_code::BAD_CONTENT_TYPE,
+                error_code::BAD_CONFIG,
+                error_code::BAD_CONFIG,
+            ]
+        );
+        assert!(problems[5].message.contains("routes[0].overflow_policy"));
+
+        // The first problem decides the exit code
+        assert_eq!(
+            report(&problems),
+            Err(error_code::GENERATOR_CHUNK_SIZE_TOO_SMALL)
+        );
+    }
+
+    #[test]
+    fn checks_prefix_and_suffix_are_used() {
+        let toml_str = r#"
+            [generator]
+            type = { name = "json" }
+
+            [[routes]]
+            path = "/api"
+            prefix = ""
+            suffix = "]"
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let problems = check(&config);
+        assert_eq!(codes(&problems), [error_code::BAD_CONFIG]);
+        assert!(problems[0].message.starts_with("routes[0].suffix"));
+
+        // The default prefix is only there for HTML
+        let toml_str = r#"
+            [generator]
+            type = { name = "json" }
+            prefix = "["
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let problems = check(&config);
+        assert_eq!(codes(&problems), [error_code::BAD_CONFIG]);
+        assert!(problems[0].message.starts_with("generator.prefix"));
+
+        let toml_str = r#"
+            [[routes]]
+            path = "/sitemap.xml"
+            type = { name = "feed", data = { format = "sitemap", links = "/maze" } }
+            prefix = "<?xml"
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let problems = check(&config);
+        assert_eq!(codes(&problems), [error_code::BAD_CONFIG]);
+        assert!(problems[0].message.starts_with("routes[0].prefix"));
+
+        let toml_str = r#"
+            [[routes]]
+            path = "/.env"
+            type = { name = "secrets", data = "dotenv" }
+            prefix = "APP_NAME=Laravel"
+            suffix = "\n"
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let problems = check(&config);
+        assert_eq!(
+            codes(&problems),
+            [error_code::BAD_CONFIG, error_code::BAD_CONFIG]
+        );
+    }
+
+    #[test]
+    fn checks_data_files() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"some text").unwrap();
+        let toml_str = format!(
+            "[[routes]]\npath = '/'\ntype = {{ name = 'static', data = '{}' }}",
+            file.path().to_string_lossy()
+        );
+        let config: Config = toml::from_str(&toml_str).unwrap();
+        assert_eq!(check(&config), vec![]);
+
+        drop(file);
+        let problems = check(&config);
+        assert_eq!(
+            codes(&problems),
+            [error_code::CANNOT_READ_GENERATOR_DATA_FILE]
+        );
+        assert!(problems[0].message.starts_with("routes[0].type"));
+    }
+
+    #[test]
+    fn checks_markov_chains_can_be_built() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"hi").unwrap();
+        let toml_str = format!(
+            "[generator]\ntype = {{ name = 'markov_chain', data = '{}' }}",
+            file.path().to_string_lossy()
+        );
+        let mut config: Config = toml::from_str(&toml_str).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        config.logging.output_path = dir.path().join("log").to_string_lossy().into_owned();
+        // Chains are only built when checking before starting
+        assert_eq!(check(&config), vec![]);
+        let problems = check_startup(&config);
+        assert_eq!(
+            codes(&problems),
+            [error_code::CANNOT_READ_GENERATOR_DATA_FILE]
+        );
+        assert!(problems[0].message.starts_with("generator.type"));
+
+        file.write_all(b" there, some more text to build a chain from.")
+            .unwrap();
+        assert_eq!(check_startup(&config), vec![]);
+    }
+
+    #[test]
+    fn checks_log_path_without_creating_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("pandoras_pot.log");
+        let mut config = Config::default();
+        config.logging.output_path = log_path.to_string_lossy().into_owned();
+        assert_eq!(check_startup(&config), vec![]);
+        assert!(!log_path.exists(), "checking created a log file");
+
+        config.logging.output_path = dir
+            .path()
+            .join("missing/pandoras_pot.log")
+            .to_string_lossy()
+            .into_owned();
+        assert_eq!(
+            codes(&check_startup(&config)),
+            [error_code::CANNOT_OPEN_LOG_FILE]
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! Finding out the IP of a client, even when it is hiding behind one or more reverse proxies.
+//!
+//! Headers such as `X-Forwarded-For` can be set by anyone, so they are only used when the
+//! connection comes from a trusted proxy. Proxies add the address they received a request from
+//! to the end of those headers, so the client is the rightmost address that is not a trusted
+//! proxy.
+
+use std::{net::IpAddr, sync::Arc};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::HeaderMap,
+};
+use ipnet::IpNet;
+
+use crate::listener::{ConnectionInfo, PeerAddr};
+
+/// Headers that contain a single address, set by some proxies instead of `Forwarded` or
+/// `X-Forwarded-For`. Checked in order.
+const SINGLE_ADDRESS_HEADERS: [&str; 4] = [
+    "CF-Connecting-IP",
+    "X-Real-IP",
+    "Client-IP",
+    "X-Originating-IP",
+];
+
+/// The resolved IP of the client, added to the extensions of every request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ClientIp(pub IpAddr);
+
+/// Networks that we trust to tell us who the client is.
+#[derive(Debug, Clone)]
+pub(crate) struct TrustedProxies {
+    networks: Arc<[IpNet]>,
+}
+
+impl TrustedProxies {
+    pub fn new(networks: &[IpNet]) -> Self {
+        Self {
+            networks: networks.iter().map(IpNet::trunc).collect(),
+        }
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.networks.iter().any(|network| network.contains(&ip))
+    }
+
+    /// Adds the [`ClientIp`] of a request to its extensions, if it can be found.
+    pub fn add_client_ip(&self, mut request: Request) -> Request {
+        let connection = request
+            .extensions()
+            .get::<ConnectInfo<ConnectionInfo>>()
+            .map(|ConnectInfo(info)| *info);
+        if let Some(ip) = self.resolve(connection, request.headers()) {
+            request.extensions_mut().insert(ClientIp(ip));
+        }
+        request
+    }
+
+    /// Finds the IP of the client that sent a request over `connection`.
+    pub fn resolve(
+        &self,
+        connection: Option<ConnectionInfo>,
+        headers: &HeaderMap,
+    ) -> Option<IpAddr> {
+        let origin = connection
+            .and_then(ConnectionInfo::origin_addr)
+            .map(|addr| addr.ip().to_canonical());
+        let trusted = match origin {
+            Some(ip) => self.contains(ip),
+            // Only someone on this machine can connect to a unix socket, such as a reverse proxy
+            None => connection.is_some_and(|c| c.peer == PeerAddr::Unix),
+        };
+
+        if trusted {
+            self.client_from_headers(headers).or(origin)
+        } else {
+            origin
+        }
+    }
+
+    /// Finds the client in headers set by trusted proxies.
+    fn client_from_headers(&self, headers: &HeaderMap) -> Option<IpAddr> {
+        let mut hops = forwarded_for(headers);
+        if hops.is_empty() {
+            hops = x_forwarded_for(headers);
+        }
+        if !hops.is_empty() {
+            return self.rightmost_untrusted(&hops);
+        }
+
+        SINGLE_ADDRESS_HEADERS.iter().find_map(|name| {
+            let value = headers.get(*name)?.to_str().ok()?;
+            parse_node(value.trim())
+        })
+    }
+
+    /// Walks the hops from the closest one, until one that is not trusted is found. Hops that
+    /// are `None` were unknown or obfuscated by a proxy, and nothing before them can be trusted.
+    fn rightmost_untrusted(&self, hops: &[Option<IpAddr>]) -> Option<IpAddr> {
+        for hop in hops.iter().rev() {
+            match hop {
+                Some(ip) if self.contains(*ip) => {}
+                hop => return *hop,
+            }
+        }
+        // Every hop was trusted, so the first one must be the client
+        hops.first().copied().flatten()
+    }
+}
+
+/// The `for` parameter of each element in all `Forwarded` headers, see RFC 7239.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all("Forwarded")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| split_unquoted(value, ','))
+        .map(|element| {
+            split_unquoted(element, ';')
+                .into_iter()
+                .find_map(|pair| {
+                    let (name, value) = pair.split_once('=')?;
+                    name.trim()
+                        .eq_ignore_ascii_case("for")
+                        .then(|| parse_node(&unquote(value.trim())))
+                })
+                // Elements without `for` are unknown hops
+                .flatten()
+        })
+        .collect()
+}
+
+/// Every address in all `X-Forwarded-For` headers.
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|node| parse_node(node.trim()))
+        .collect()
+}
+
+/// Splits `value` on `separator`, except in quoted strings.
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(&value[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// Removes the quotes and escapes of a quoted string.
+fn unquote(value: &str) -> String {
+    let Some(inner) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    else {
+        return value.to_string();
+    };
+
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut escaped = false;
+    for c in inner.chars() {
+        if c == '\\' && !escaped {
+            escaped = true;
+        } else {
+            unquoted.push(c);
+            escaped = false;
+        }
+    }
+    unquoted
+}
+
+/// Parses an address that may have a port, such as `192.0.2.1`, `192.0.2.1:4711`, `[2001:db8::1]`
+/// or `[2001:db8::1]:4711`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+    let host = match node.strip_prefix('[') {
+        Some(rest) => rest.split_once(']')?.0,
+        None => node.split_once(':')?.0,
+    };
+    host.parse::<IpAddr>().ok().map(|ip| ip.to_canonical())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use axum::http::{HeaderMap, HeaderName, HeaderValue};
+
+    use crate::listener::{ConnectionInfo, PeerAddr};
+
+    use super::{parse_node, TrustedProxies};
+
+    fn trusted() -> TrustedProxies {
+        TrustedProxies::new(&[
+            "127.0.0.0/8".parse().unwrap(),
+            "10.0.0.0/8".parse().unwrap(),
+        ])
+    }
+
+    fn tcp(addr: &str) -> Option<ConnectionInfo> {
+        Some(addr.parse::<SocketAddr>().unwrap().into())
+    }
+
+    fn header_map(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn untrusted_peers_cannot_spoof() {
+        let headers = header_map(&[("x-forwarded-for", "192.0.2.1"), ("x-real-ip", "192.0.2.2")]);
+        assert_eq!(
+            trusted().resolve(tcp("203.0.113.7:4711"), &headers),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn picks_rightmost_untrusted_hop() {
+        let headers = header_map(&[("x-forwarded-for", "192.0.2.1, 203.0.113.7, 10.1.2.3")]);
+        assert_eq!(
+            trusted().resolve(tcp("127.0.0.1:4711"), &headers),
+            ip("203.0.113.7")
+        );
+
+        // Only trusted proxies all the way
+        let headers = header_map(&[("x-forwarded-for", "10.0.0.1, 10.0.0.2")]);
+        assert_eq!(
+            trusted().resolve(tcp("127.0.0.1:4711"), &headers),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn parses_forwarded() {
+        let headers = header_map(&[
+            ("forwarded", r#"for=192.0.2.60;proto=http;by=203.0.113.43"#),
+            (
+                "forwarded",
+                r#"For="[2001:db8:cafe::17]:4711", for=10.0.0.1"#,
+            ),
+            // Takes precedence
+            ("x-forwarded-for", "192.0.2.1"),
+        ]);
+        assert_eq!(
+            trusted().resolve(tcp("127.0.0.1:4711"), &headers),
+            ip("2001:db8:cafe::17")
+        );
+
+        // Nothing can be known from behind an obfuscated hop
+        let headers = header_map(&[("forwarded", "for=192.0.2.60, for=_hidden")]);
+        assert_eq!(
+            trusted().resolve(tcp("10.0.0.1:4711"), &headers),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn trusts_unix_sockets_and_proxy_protocol() {
+        let headers = header_map(&[("x-real-ip", "192.0.2.1")]);
+        let unix = Some(ConnectionInfo {
+            peer: PeerAddr::Unix,
+            proxied: None,
+        });
+        assert_eq!(trusted().resolve(unix, &headers), ip("192.0.2.1"));
+        assert_eq!(trusted().resolve(None, &headers), None);
+
+        // The PROXY protocol header says who is connecting, so it is checked instead of the peer
+        let proxied = Some(ConnectionInfo {
+            peer: PeerAddr::Tcp("127.0.0.1:4711".parse().unwrap()),
+            proxied: Some("203.0.113.7:4711".parse().unwrap()),
+        });
+        assert_eq!(trusted().resolve(proxied, &headers), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_are_trusted() {
+        let headers = header_map(&[("x-forwarded-for", "192.0.2.1")]);
+        assert_eq!(
+            trusted().resolve(tcp("[::ffff:127.0.0.1]:4711"), &headers),
+            ip("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn parses_nodes() {
+        assert_eq!(parse_node("192.0.2.1"), ip("192.0.2.1"));
+        assert_eq!(parse_node("192.0.2.1:4711"), ip("192.0.2.1"));
+        assert_eq!(parse_node("2001:db8::1"), ip("2001:db8::1"));
+        assert_eq!(parse_node("[2001:db8::1]:4711"), ip("2001:db8::1"));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+    }
+}
@@ -0,0 +1,465 @@
+//! Limits on how much a single client can use, telling clients apart by their IP.
+//!
+//! Every client gets a token bucket for its requests and a semaphore for its streams. Only a
+//! bounded number of clients are remembered, forgetting the ones that were least recently seen.
+
+use std::{
+    net::IpAddr,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use axum::{
+    http::{
+        header::{CONTENT_TYPE, RETRY_AFTER},
+        HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use lru::LruCache;
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+use crate::{
+    config::{ClientLimitsConfig, LimitAction},
+    generator::QueuedGuard,
+};
+
+/// Sent to clients over their limits when using [`LimitAction::Static`].
+const STATIC_BODY: &str = "<!DOCTYPE html><html><body><p>Loading...</p></body></html>";
+
+/// How many of the least recently seen clients are looked through for an idle one to forget,
+/// which keeps remembering a new client quick when most clients are busy.
+const MAX_EVICTION_SCAN: usize = 16;
+
+/// What we know about a single client.
+#[derive(Debug)]
+struct ClientState {
+    /// Requests the client can make right now. Negative if requests are queued.
+    tokens: f64,
+    /// When `tokens` was last refilled.
+    refilled: Instant,
+    /// Permits for the streams of the client.
+    streams: Arc<Semaphore>,
+    /// Requests of the client waiting for either limit.
+    queued: Arc<AtomicUsize>,
+}
+
+impl ClientState {
+    /// Whether the client has no streams open and no requests queued, so that forgetting it
+    /// does not let it get around its limits.
+    fn is_idle(&self) -> bool {
+        // Every permit, and every request waiting for one, holds on to the semaphore
+        Arc::strong_count(&self.streams) == 1 && self.queued.load(Ordering::SeqCst) == 0
+    }
+}
+
+/// Per-client limits, shared by everything serving generated data.
+///
+/// Cheap to clone, as internals are wrapped in [`Arc`].
+#[derive(Debug, Clone)]
+pub(crate) struct ClientLimits {
+    config: Arc<ClientLimitsConfig>,
+    clients: Arc<Mutex<LruCache<IpAddr, ClientState>>>,
+}
+
+/// Held for as long as a client is streaming, counting towards its `max_concurrent`.
+#[derive(Debug, Default)]
+pub(crate) struct ClientPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ClientLimits {
+    /// Creates new limits, returning `None` if `config.max_clients` is 0.
+    pub fn new(config: ClientLimitsConfig) -> Option<Self> {
+        let max_clients = NonZeroUsize::new(config.max_clients)?;
+        Some(Self {
+            config: Arc::new(config),
+            clients: Arc::new(Mutex::new(LruCache::new(max_clients))),
+        })
+    }
+
+    pub fn config(&self) -> &ClientLimitsConfig {
+        &self.config
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.config.rate_limit != 0 || self.config.max_concurrent != 0
+    }
+
+    /// Tokens added to the bucket of a client each second.
+    fn refill_rate(&self) -> f64 {
+        self.config.rate_limit as f64 / self.config.rate_limit_period as f64
+    }
+
+    /// Checks that a request from `ip` is within its limits, waiting if they are configured to
+    /// be queued. Clients without a known IP are never limited.
+    ///
+    /// Returns the response to send instead if the client is over its limits.
+    pub async fn admit(&self, ip: Option<IpAddr>) -> Result<ClientPermit, Response> {
+        let Some(ip) = ip.filter(|_| self.is_enabled()) else {
+            return Ok(ClientPermit::default());
+        };
+
+        if let Some((wait, queued)) = self.take_token(ip) {
+            tracing::info!("Client '{ip}' is over its rate limit");
+            match (self.config.rate_limit_action, queued) {
+                (LimitAction::Queue, Some(_queued)) => tokio::time::sleep(wait).await,
+                (LimitAction::Queue, None) => {
+                    tracing::info!("Client '{ip}' has too many requests queued");
+                    return Err(limited_response(LimitAction::TooManyRequests, Some(wait)));
+                }
+                (action, _) => return Err(limited_response(action, Some(wait))),
+            }
+        }
+
+        if self.config.max_concurrent == 0 {
+            return Ok(ClientPermit::default());
+        }
+        let (streams, queued) = self.streams(ip);
+        if let Ok(permit) = streams.clone().try_acquire_owned() {
+            return Ok(ClientPermit {
+                _permit: Some(permit),
+            });
+        }
+        tracing::info!("Client '{ip}' has too many streams open");
+        match self.config.max_concurrent_action {
+            LimitAction::Queue => {
+                let Some(_queued) = QueuedGuard::new(&queued, self.config.max_queued) else {
+                    tracing::info!("Client '{ip}' has too many requests queued");
+                    return Err(limited_response(LimitAction::TooManyRequests, None));
+                };
+                // The semaphore is never closed
+                let permit = streams.acquire_owned().await.unwrap();
+                Ok(ClientPermit {
+                    _permit: Some(permit),
+                })
+            }
+            action => Err(limited_response(action, None)),
+        }
+    }
+
+    /// Takes a token from the bucket of `ip`. Returns how long it takes until the token would
+    /// have been available if the bucket was empty, in which case a token is only taken if
+    /// requests are queued and the client has room for another one in the queue. The request
+    /// is counted as queued for as long as the returned guard is held.
+    fn take_token(&self, ip: IpAddr) -> Option<(Duration, Option<QueuedGuard>)> {
+        if self.config.rate_limit == 0 {
+            return None;
+        }
+
+        let capacity = self.config.rate_limit as f64;
+        let rate = self.refill_rate();
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        let client = self.client(&mut clients, ip, now);
+
+        let elapsed = now.duration_since(client.refilled).as_secs_f64();
+        client.tokens = (client.tokens + elapsed * rate).min(capacity);
+        client.refilled = now;
+
+        if client.tokens >= 1.0 {
+            client.tokens -= 1.0;
+            return None;
+        }
+
+        let wait = Duration::from_secs_f64((1.0 - client.tokens) / rate);
+        if self.config.rate_limit_action != LimitAction::Queue {
+            return Some((wait, None));
+        }
+        let queued = QueuedGuard::new(&client.queued, self.config.max_queued);
+        if queued.is_some() {
+            // Reserve the token, so that queued requests are let through one at a time
+            client.tokens -= 1.0;
+        }
+        Some((wait, queued))
+    }
+
+    /// The semaphore limiting the streams of `ip`, and its count of queued requests.
+    fn streams(&self, ip: IpAddr) -> (Arc<Semaphore>, Arc<AtomicUsize>) {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        let client = self.client(&mut clients, ip, now);
+        (client.streams.clone(), client.queued.clone())
+    }
+
+    /// The state of `ip`, which is remembered if it is new. If there are too many clients to
+    /// remember another one, the least recently seen idle client is forgotten, or the least
+    /// recently seen one if none of the [`MAX_EVICTION_SCAN`] least recently seen are idle.
+    fn client<'a>(
+        &self,
+        clients: &'a mut LruCache<IpAddr, ClientState>,
+        ip: IpAddr,
+        now: Instant,
+    ) -> &'a mut ClientState {
+        if !clients.contains(&ip) && clients.len() == clients.cap().get() {
+            // Forgetting a client with open streams would let it open as many again
+            let idle = clients
+                .iter()
+                .rev()
+                .take(MAX_EVICTION_SCAN)
+                .find(|(_, c)| c.is_idle())
+                .map(|(ip, _)| *ip);
+            if let Some(idle) = idle {
+                clients.pop(&idle);
+            }
+        }
+        clients.get_or_insert_mut(ip, || self.new_client(now))
+    }
+
+    fn new_client(&self, now: Instant) -> ClientState {
+        ClientState {
+            tokens: self.config.rate_limit as f64,
+            refilled: now,
+            streams: Arc::new(Semaphore::new(self.config.max_concurrent)),
+            queued: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+/// The response sent to a client that is over its limits.
+fn limited_response(action: LimitAction, retry_after: Option<Duration>) -> Response {
+    match action {
+        LimitAction::Static => {
+            ([(CONTENT_TYPE, "text/html; charset=utf-8")], STATIC_BODY).into_response()
+        }
+        // Queued requests never get here, but are rejected if they somehow do
+        LimitAction::TooManyRequests | LimitAction::Queue => {
+            let mut response = StatusCode::TOO_MANY_REQUESTS.into_response();
+            if let Some(retry_after) = retry_after {
+                // Round up, retrying too early would be pointless
+                let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(seconds));
+            }
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, time::Duration};
+
+    use axum::http::{header::RETRY_AFTER, StatusCode};
+
+    use crate::config::{ClientLimitsConfig, LimitAction};
+
+    use super::{ClientLimits, MAX_EVICTION_SCAN};
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn unlimited_by_default() {
+        let limits = ClientLimits::new(ClientLimitsConfig::default()).unwrap();
+        for _ in 0..100 {
+            limits.admit(ip("192.0.2.1")).await.unwrap();
+        }
+        assert_eq!(limits.clients.lock().unwrap().len(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limited() {
+        let limits = ClientLimits::new(ClientLimitsConfig {
+            rate_limit: 2,
+            rate_limit_period: 10,
+            ..Default::default()
+        })
+        .unwrap();
+
+        limits.admit(ip("192.0.2.1")).await.unwrap();
+        limits.admit(ip("192.0.2.1")).await.unwrap();
+        let response = limits.admit(ip("192.0.2.1")).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "5");
+
+        // Other clients have their own limits, and unknown ones have none
+        limits.admit(ip("192.0.2.2")).await.unwrap();
+        for _ in 0..10 {
+            limits.admit(None).await.unwrap();
+        }
+
+        // A token is added every 5 seconds
+        tokio::time::advance(Duration::from_secs(5)).await;
+        limits.admit(ip("192.0.2.1")).await.unwrap();
+        assert!(limits.admit(ip("192.0.2.1")).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_queue() {
+        let limits = ClientLimits::new(ClientLimitsConfig {
+            rate_limit: 1,
+            rate_limit_period: 10,
+            rate_limit_action: LimitAction::Queue,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let start = tokio::time::Instant::now();
+        for _ in 0..3 {
+            limits.admit(ip("192.0.2.1")).await.unwrap();
+        }
+        assert_eq!(start.elapsed(), Duration::from_secs(20));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_queue_is_bounded() {
+        let limits = ClientLimits::new(ClientLimitsConfig {
+            rate_limit: 1,
+            rate_limit_period: 10,
+            rate_limit_action: LimitAction::Queue,
+            max_queued: 1,
+            ..Default::default()
+        })
+        .unwrap();
+
+        limits.admit(ip("192.0.2.1")).await.unwrap();
+        let queued = tokio::spawn({
+            let limits = limits.clone();
+            async move { limits.admit(ip("192.0.2.1")).await.unwrap() }
+        });
+        tokio::task::yield_now().await;
+        let response = limits.admit(ip("192.0.2.1")).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "20");
+
+        // Rejected requests do not reserve a token
+        queued.await.unwrap();
+        tokio::time::advance(Duration::from_secs(10)).await;
+        limits.admit(ip("192.0.2.1")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrency_limited() {
+        let limits = ClientLimits::new(ClientLimitsConfig {
+            max_concurrent: 1,
+            max_concurrent_action: LimitAction::Static,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let permit = limits.admit(ip("192.0.2.1")).await.unwrap();
+        let response = limits.admit(ip("192.0.2.1")).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::OK);
+        limits.admit(ip("192.0.2.2")).await.unwrap();
+
+        // Ending the stream lets the client start another one
+        drop(permit);
+        limits.admit(ip("192.0.2.1")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrency_queue() {
+        let limits = ClientLimits::new(ClientLimitsConfig {
+            max_concurrent: 1,
+            max_concurrent_action: LimitAction::Queue,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let permit = limits.admit(ip("192.0.2.1")).await.unwrap();
+        let queued = tokio::spawn({
+            let limits = limits.clone();
+            async move { limits.admit(ip("192.0.2.1")).await.unwrap() }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!queued.is_finished());
+
+        drop(permit);
+        queued.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrency_queue_is_bounded() {
+        let limits = ClientLimits::new(ClientLimitsConfig {
+            max_concurrent: 1,
+            max_concurrent_action: LimitAction::Queue,
+            max_queued: 1,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let permit = limits.admit(ip("192.0.2.1")).await.unwrap();
+        let queued = tokio::spawn({
+            let limits = limits.clone();
+            async move { limits.admit(ip("192.0.2.1")).await.unwrap() }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let response = limits.admit(ip("192.0.2.1")).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        drop(permit);
+        queued.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn remembers_clients_with_open_streams() {
+        let limits = ClientLimits::new(ClientLimitsConfig {
+            max_concurrent: 1,
+            max_clients: 2,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let _permit = limits.admit(ip("192.0.2.1")).await.unwrap();
+        drop(limits.admit(ip("192.0.2.2")).await.unwrap());
+        limits.admit(ip("192.0.2.3")).await.unwrap();
+
+        // 192.0.2.1 was seen least recently, but is still streaming
+        assert!(limits.admit(ip("192.0.2.1")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn forgets_busy_clients_if_no_idle_ones_are_found() {
+        let limits = ClientLimits::new(ClientLimitsConfig {
+            max_concurrent: 1,
+            max_clients: MAX_EVICTION_SCAN + 1,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut permits = Vec::new();
+        for i in 0..MAX_EVICTION_SCAN {
+            permits.push(limits.admit(ip(&format!("192.0.2.{i}"))).await.unwrap());
+        }
+        drop(limits.admit(ip("198.51.100.1")).await.unwrap());
+        limits.admit(ip("198.51.100.2")).await.unwrap();
+
+        // Only the least recently seen clients are looked through, so the first one is forgotten
+        limits.admit(ip("192.0.2.0")).await.unwrap();
+        assert!(limits.admit(ip("192.0.2.1")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn forgets_least_recently_seen_clients() {
+        let limits = ClientLimits::new(ClientLimitsConfig {
+            rate_limit: 1,
+            max_clients: 2,
+            ..Default::default()
+        })
+        .unwrap();
+
+        limits.admit(ip("192.0.2.1")).await.unwrap();
+        limits.admit(ip("192.0.2.2")).await.unwrap();
+        assert!(limits.admit(ip("192.0.2.1")).await.is_err());
+        limits.admit(ip("192.0.2.3")).await.unwrap();
+
+        // 192.0.2.2 was seen least recently, so it starts over
+        assert_eq!(limits.clients.lock().unwrap().len(), 2);
+        limits.admit(ip("192.0.2.2")).await.unwrap();
+        assert!(ClientLimits::new(ClientLimitsConfig {
+            max_clients: 0,
+            ..Default::default()
+        })
+        .is_none());
+    }
+}
@@ -1,15 +1,24 @@
 //! This module contains the types used for configuration.
 
 use std::{
-    fmt,
+    error, fmt, io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use serde::{Deserialize, Serialize};
 
+use crate::error_code;
+
 /// Configuration for `pandoras_pot`.
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub(crate) struct Config {
+    /// Reject the config if it has unknown keys, instead of just warning about them.
+    #[serde(default)]
+    pub strict: bool,
+
     /// Configuration related to HTTP server.
     #[serde(default)]
     pub http: HttpConfig,
@@ -21,6 +30,11 @@ pub(crate) struct Config {
     /// Configuration related to logs.
     #[serde(default)]
     pub logging: LoggingConfig,
+
+    /// Routes with their own generator settings. Values not set for a route fall back to the
+    /// ones in `http` and `generator`.
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
 }
 
 impl Config {
@@ -30,25 +44,228 @@ impl Config {
         Some(dir)
     }
 
-    pub fn from_path(path: &Path) -> Option<Self> {
-        let toml = std::fs::read_to_string(path).ok()?;
-        toml::from_str(&toml).ok()
+    /// Reads the config at `path`, along with any keys in it that are not used. Unknown keys
+    /// are an error if `strict` is set, either here or in the config.
+    pub fn from_path(path: &Path, strict: bool) -> Result<(Self, Vec<UnknownKey>), ConfigError> {
+        let toml = std::fs::read_to_string(path).map_err(|error| match error.kind() {
+            io::ErrorKind::NotFound => ConfigError::NotFound(path.to_path_buf()),
+            _ => ConfigError::Io(path.to_path_buf(), error),
+        })?;
+        let parse_error =
+            |error| ConfigError::Parse(Box::new(ParseError::new(path.to_path_buf(), &toml, error)));
+
+        let mut unknown_keys = Vec::new();
+        let deserializer = toml::Deserializer::parse(&toml).map_err(parse_error)?;
+        let config: Self = serde_ignored::deserialize(deserializer, |key| {
+            unknown_keys.push(UnknownKey::new(key_path(&key)));
+        })
+        .map_err(parse_error)?;
+
+        if (strict || config.strict) && !unknown_keys.is_empty() {
+            return Err(ConfigError::UnknownKeys(path.to_path_buf(), unknown_keys));
+        }
+        Ok((config, unknown_keys))
     }
+}
 
-    pub fn read_from_default_path() -> Option<Self> {
-        if let Some(path) = Self::default_path() {
-            Self::from_path(&path)
-        } else {
-            None
+/// Keys that used to be in the config, and how to do the same thing now.
+const OLD_KEYS: [(&str, &str); 2] = [
+    (
+        "generator.min_chunk_size",
+        "chunks have a fixed size now, use `generator.chunk_size` instead",
+    ),
+    (
+        "generator.max_chunk_size",
+        "chunks have a fixed size now, use `generator.chunk_size` instead",
+    ),
+];
+
+/// A key in a config that is not used for anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UnknownKey {
+    /// Full path of the key, such as `generator.min_chunk_size` or `routes[0].typo`.
+    pub path: String,
+    /// How to migrate if the key used to be in the config.
+    pub hint: Option<&'static str>,
+}
+
+impl UnknownKey {
+    fn new(path: String) -> Self {
+        let hint = OLD_KEYS
+            .iter()
+            .find(|(old, _)| *old == path)
+            .map(|(_, hint)| *hint);
+        Self { path, hint }
+    }
+}
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown config key `{}`", self.path)?;
+        if let Some(hint) = self.hint {
+            write!(f, " ({hint})")?;
         }
+        Ok(())
+    }
+}
+
+/// Writes `path` like keys are written in TOML, such as `http.tls.cert_path`.
+fn key_path(path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Root => String::new(),
+        serde_ignored::Path::Seq { parent, index } => format!("{}[{index}]", key_path(parent)),
+        serde_ignored::Path::Map { parent, key } => match key_path(parent) {
+            parent if parent.is_empty() => key.clone(),
+            parent => format!("{parent}.{key}"),
+        },
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => key_path(parent),
     }
 }
 
+/// Why a config could not be read.
+#[derive(Debug)]
+pub(crate) enum ConfigError {
+    /// There is no file at the path.
+    NotFound(PathBuf),
+    /// The file could not be read.
+    Io(PathBuf, io::Error),
+    /// The file is not valid TOML, or not a valid config.
+    Parse(Box<ParseError>),
+    /// The config has unknown keys, and is strict about it.
+    UnknownKeys(PathBuf, Vec<UnknownKey>),
+}
+
+impl ConfigError {
+    /// The code to exit with when the config cannot be used.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::NotFound(_) => error_code::CONFIG_NOT_FOUND,
+            Self::Io(..) => error_code::CANNOT_READ_CONFIG,
+            Self::Parse(_) => error_code::UNPARSEABLE_CONFIG,
+            Self::UnknownKeys(..) => error_code::UNKNOWN_CONFIG_KEYS,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(path) => write!(f, "no config found at '{}'", path.to_string_lossy()),
+            Self::Io(path, error) => write!(
+                f,
+                "cannot read config at '{}' due to error:\n\t{error}",
+                path.to_string_lossy()
+            ),
+            Self::Parse(error) => write!(f, "{error}"),
+            Self::UnknownKeys(path, keys) => {
+                write!(
+                    f,
+                    "config at '{}' is strict, but has:",
+                    path.to_string_lossy()
+                )?;
+                for key in keys {
+                    write!(f, "\n\t{key}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::NotFound(_) | Self::UnknownKeys(..) => None,
+            Self::Io(_, error) => Some(error),
+            Self::Parse(error) => Some(&error.error),
+        }
+    }
+}
+
+/// Where in a config file parsing failed.
+#[derive(Debug)]
+pub(crate) struct ParseError {
+    pub path: PathBuf,
+    /// Line of the error, starting at 1.
+    pub line: Option<usize>,
+    /// Column of the error, in characters and starting at 1.
+    pub column: Option<usize>,
+    /// The key with the bad value, including its table, such as `http.port`.
+    pub key: Option<String>,
+    error: toml::de::Error,
+}
+
+impl ParseError {
+    fn new(path: PathBuf, toml: &str, error: toml::de::Error) -> Self {
+        let position = error.span().map(|span| {
+            // Spans can point past the end of the file
+            let before = toml.get(..span.start).unwrap_or(toml);
+            let line = before.matches('\n').count();
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            (line, before[line_start..].chars().count() + 1)
+        });
+        Self {
+            path,
+            line: position.map(|(line, _)| line + 1),
+            column: position.map(|(_, column)| column),
+            key: position.and_then(|(line, _)| key_at(toml, line)),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cannot parse config at '{}'",
+            self.path.to_string_lossy()
+        )?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, " (line {line}, column {column})")?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, ", bad value for `{key}`")?;
+        }
+        write!(f, ":\n{}", self.error)
+    }
+}
+
+/// The key set on line `line` (starting at 0) of `toml`, prefixed by its table. Only handles
+/// plain `key = value` lines, which is where type errors usually are.
+fn key_at(toml: &str, line: usize) -> Option<String> {
+    let lines: Vec<&str> = toml.lines().collect();
+    let (key, _) = lines.get(line)?.split_once('=')?;
+    let key = key.trim();
+    if key.is_empty() || key.starts_with(['#', '{', '[']) {
+        return None;
+    }
+
+    let table = lines[..line]
+        .iter()
+        .map(|l| l.trim())
+        .rfind(|l| l.starts_with('['))
+        .map(|l| l.trim_matches(['[', ']']).trim());
+    Some(match table {
+        Some(table) => format!("{table}.{key}"),
+        None => key.to_string(),
+    })
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub(crate) struct HttpConfig {
-    /// Port to listen on.
+    /// Port to listen on, on all IPv4 addresses. Only used if `http.listen` is empty.
     #[serde(default = "default_http_port")]
     pub port: String,
+    /// Addresses to listen on, such as `"127.0.0.1:8080"`, `"[::]:8080"` or
+    /// `"unix:/run/pandoras_pot.sock"`. A listener can also be given as a table with its own
+    /// routes, which replace `http.routes` and `http.catch_all` for that listener, and with
+    /// the mode and owner of a unix socket, or with `proxy_protocol` if it is behind a load
+    /// balancer using the PROXY protocol.
+    #[serde(default)]
+    pub listen: Vec<ListenerConfig>,
     /// Routes to be handled. Is overriden by `http.catch_all`.
     #[serde(default = "default_http_routes")]
     pub routes: Vec<String>,
@@ -56,7 +273,7 @@ pub(crate) struct HttpConfig {
     #[serde(default = "default_http_catch_all")]
     pub catch_all: bool,
     /// How many connections that can be made over `http.rate_limit_period` seconds. Will
-    /// not set any limit if set to 0.
+    /// not set any limit if set to 0. Changes are only applied after a restart.
     #[serde(default = "default_http_rate_limit")]
     pub rate_limit: u64,
     /// Amount of seconds that `http.rate_limit` checks on. Does nothing if rate limit is set
@@ -69,25 +286,52 @@ pub(crate) struct HttpConfig {
     #[serde(default = "default_http_health_port_enabled")]
     pub health_port_enabled: bool,
     /// Port to be used for health checks. Should probably not be accessible from the
-    /// outside. Has no effect if `http.health_port_enabled` is `false`.
+    /// outside. Has no effect if `http.health_port_enabled` is `false`, or if
+    /// `http.health_listen` is set.
     #[serde(default = "default_http_health_port")]
     pub health_port: String,
+    /// Addresses to serve health checks on, written like in `http.listen` but without routes.
+    /// Health checks are served on these even if `http.health_port_enabled` is `false`.
+    #[serde(default)]
+    pub health_listen: Vec<ListenerConfig>,
     /// The `Content-Type` header set in responses.
     #[serde(default = "default_http_content_type")]
     pub content_type: String,
+    /// Networks of reverse proxies that are trusted to tell us the address of the client,
+    /// such as `"10.0.0.0/8"`. Headers like `X-Forwarded-For` are ignored for anyone else.
+    /// Connections over unix sockets are always trusted.
+    #[serde(default = "default_http_trusted_proxies")]
+    pub trusted_proxies: Vec<IpNet>,
+    /// Limits for each client, telling them apart by their IP.
+    #[serde(default)]
+    pub client_limits: ClientLimitsConfig,
+    /// Seconds that open streams get to end on their own when shutting down, before they are
+    /// closed. `0` closes them right away.
+    #[serde(default = "default_http_drain_period")]
+    pub drain_period: u64,
+    /// Serve HTTPS instead of plain HTTP. Requires `pandoras_pot` to be built with the `tls`
+    /// feature. Unix sockets always serve plain HTTP.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
 }
 
 impl Default for HttpConfig {
     fn default() -> Self {
         Self {
             port: default_http_port(),
+            listen: Vec::new(),
             routes: default_http_routes(),
             catch_all: default_http_catch_all(),
             rate_limit: default_http_rate_limit(),
             rate_limit_period: default_http_rate_limit(),
             health_port_enabled: default_http_health_port_enabled(),
             health_port: default_http_health_port(),
+            health_listen: Vec::new(),
             content_type: default_http_content_type(),
+            trusted_proxies: default_http_trusted_proxies(),
+            client_limits: ClientLimitsConfig::default(),
+            drain_period: default_http_drain_period(),
+            tls: None,
         }
     }
 }
@@ -127,6 +371,285 @@ fn default_http_content_type() -> String {
     "text/html; charset=utf-8".to_string()
 }
 
+fn default_http_trusted_proxies() -> Vec<IpNet> {
+    // Reverse proxies on the same machine
+    vec![
+        Ipv4Net::new_assert(Ipv4Addr::new(127, 0, 0, 0), 8).into(),
+        Ipv6Net::new_assert(Ipv6Addr::LOCALHOST, 128).into(),
+    ]
+}
+
+const fn default_http_drain_period() -> u64 {
+    10
+}
+
+/// Limits that apply to each client on its own, so that a single aggressive client cannot take
+/// all of `generator.max_concurrent` for itself.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct ClientLimitsConfig {
+    /// How many requests a single client can make over `rate_limit_period` seconds. `0` means
+    /// no limit.
+    #[serde(default = "default_client_limits_rate_limit")]
+    pub rate_limit: u64,
+    /// Amount of seconds that `rate_limit` checks on. Does nothing if `rate_limit` is set to 0.
+    #[serde(default = "default_client_limits_rate_limit_period")]
+    pub rate_limit_period: u64,
+    /// What to do with requests over `rate_limit`.
+    #[serde(default = "default_client_limits_action")]
+    pub rate_limit_action: LimitAction,
+    /// How many streams a single client can have open at once. `0` means no limit.
+    #[serde(default = "default_client_limits_max_concurrent")]
+    pub max_concurrent: usize,
+    /// What to do with requests over `max_concurrent`.
+    #[serde(default = "default_client_limits_action")]
+    pub max_concurrent_action: LimitAction,
+    /// How many requests a single client can have queued at once by either limit, when using
+    /// [`LimitAction::Queue`]. Requests over this get a `429 Too Many Requests`. `0` means no
+    /// limit.
+    #[serde(default = "default_client_limits_max_queued")]
+    pub max_queued: usize,
+    /// How many clients to keep track of. The least recently seen clients are forgotten when
+    /// there are more than this, preferring ones without streams open or requests queued among
+    /// the 16 least recently seen. Must be >= 1.
+    #[serde(default = "default_client_limits_max_clients")]
+    pub max_clients: usize,
+}
+
+impl Default for ClientLimitsConfig {
+    fn default() -> Self {
+        Self {
+            rate_limit: default_client_limits_rate_limit(),
+            rate_limit_period: default_client_limits_rate_limit_period(),
+            rate_limit_action: default_client_limits_action(),
+            max_concurrent: default_client_limits_max_concurrent(),
+            max_concurrent_action: default_client_limits_action(),
+            max_queued: default_client_limits_max_queued(),
+            max_clients: default_client_limits_max_clients(),
+        }
+    }
+}
+
+/// What to do with a request from a client that is over one of its limits.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LimitAction {
+    /// Respond with `429 Too Many Requests`.
+    TooManyRequests,
+    /// Respond with a small static page, which is cheap to send.
+    Static,
+    /// Wait until the client is within its limits again.
+    Queue,
+}
+
+// Note naming convention for these
+
+const fn default_client_limits_rate_limit() -> u64 {
+    0
+}
+
+const fn default_client_limits_rate_limit_period() -> u64 {
+    // 1 minute
+    60
+}
+
+const fn default_client_limits_action() -> LimitAction {
+    LimitAction::TooManyRequests
+}
+
+const fn default_client_limits_max_concurrent() -> usize {
+    0
+}
+
+const fn default_client_limits_max_queued() -> usize {
+    10
+}
+
+const fn default_client_limits_max_clients() -> usize {
+    10_000
+}
+
+/// An address to serve generated data on, optionally with its own set of routes.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "ListenerConfigRepr", into = "ListenerConfigRepr")]
+pub(crate) struct ListenerConfig {
+    pub address: ListenAddress,
+    /// Routes served on this listener. Routes that are also in `[[routes]]` use the settings
+    /// there. `None` means that `http.routes` and `http.catch_all` are used.
+    pub routes: Option<Vec<String>>,
+    /// File mode of a unix socket, such as `0o660`. Uses the umask of the process if not set.
+    pub mode: Option<u32>,
+    /// User owning a unix socket, either a name or a numeric id.
+    pub owner: Option<String>,
+    /// Group owning a unix socket, either a name or a numeric id.
+    pub group: Option<String>,
+    /// Expect connections to start with a PROXY protocol header (version 1 or 2), telling us
+    /// the address of the client. Connections without one are dropped.
+    pub proxy_protocol: bool,
+}
+
+impl ListenerConfig {
+    /// A listener on `address`, without any specific settings.
+    pub fn new(address: ListenAddress) -> Self {
+        Self {
+            address,
+            routes: None,
+            mode: None,
+            owner: None,
+            group: None,
+            proxy_protocol: false,
+        }
+    }
+}
+
+/// How a [`ListenerConfig`] is written in the config, either as just an address or as a table.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ListenerConfigRepr {
+    Address(String),
+    Table {
+        address: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        routes: Option<Vec<String>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        owner: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        proxy_protocol: bool,
+    },
+}
+
+impl TryFrom<ListenerConfigRepr> for ListenerConfig {
+    type Error = String;
+
+    fn try_from(repr: ListenerConfigRepr) -> Result<Self, Self::Error> {
+        let listener = match repr {
+            ListenerConfigRepr::Address(address) => Self::new(address.parse()?),
+            ListenerConfigRepr::Table {
+                address,
+                routes,
+                mode,
+                owner,
+                group,
+                proxy_protocol,
+            } => Self {
+                address: address.parse()?,
+                routes,
+                mode,
+                owner,
+                group,
+                proxy_protocol,
+            },
+        };
+
+        let has_socket_options =
+            listener.mode.is_some() || listener.owner.is_some() || listener.group.is_some();
+        if has_socket_options && !matches!(listener.address, ListenAddress::Unix(_)) {
+            return Err(format!(
+                "mode, owner and group can only be set for unix sockets, not '{}'",
+                listener.address
+            ));
+        }
+        if listener.mode.is_some_and(|mode| mode > 0o7777) {
+            return Err(format!(
+                "invalid mode for unix socket '{}'",
+                listener.address
+            ));
+        }
+
+        Ok(listener)
+    }
+}
+
+impl From<ListenerConfig> for ListenerConfigRepr {
+    fn from(listener: ListenerConfig) -> Self {
+        match listener {
+            ListenerConfig {
+                address,
+                routes: None,
+                mode: None,
+                owner: None,
+                group: None,
+                proxy_protocol: false,
+            } => Self::Address(address.to_string()),
+            ListenerConfig {
+                address,
+                routes,
+                mode,
+                owner,
+                group,
+                proxy_protocol,
+            } => Self::Table {
+                address: address.to_string(),
+                routes,
+                mode,
+                owner,
+                group,
+                proxy_protocol,
+            },
+        }
+    }
+}
+
+/// Somewhere to listen for connections.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum ListenAddress {
+    /// A TCP address, such as `127.0.0.1:8080` or `[::]:8080`.
+    Tcp(SocketAddr),
+    /// Path to a unix domain socket, written as `unix:/run/pandoras_pot.sock`.
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(format!(
+                    "invalid listen address '{s}', unix socket path is empty"
+                ));
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+
+        s.parse().map(Self::Tcp).map_err(|e| {
+            format!("invalid listen address '{s}' ({e}), expected something like '127.0.0.1:8080', '[::1]:8080' or 'unix:/run/pandoras_pot.sock'")
+        })
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.to_string_lossy()),
+        }
+    }
+}
+
+/// Configuration for serving HTTPS directly, without a reverse proxy.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct TlsConfig {
+    /// Path to a PEM file with the certificate chain to use.
+    pub cert_path: PathBuf,
+    /// Path to a PEM file with the private key of the certificate.
+    pub key_path: PathBuf,
+    /// How often, in seconds, to check if the certificate or key has changed and reload them.
+    /// `0` disables reloading.
+    #[serde(default = "default_tls_reload_interval")]
+    pub reload_interval: u64,
+}
+
+// Note naming convention for these
+
+const fn default_tls_reload_interval() -> u64 {
+    // 1 hour
+    60 * 60
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub(crate) struct GeneratorConfig {
     /// The size of each generated chunk in bytes. Has a big impact on performance, so
@@ -165,6 +688,90 @@ pub(crate) struct GeneratorConfig {
     /// output look like a valid stream of JSON that will eventually end (it won't).
     #[serde(default = "default_generator_prefix")]
     pub prefix: String,
+
+    /// Suffix sent as a last message when the stream is ended by `time_limit` or `size_limit`,
+    /// or by shutting down. Can be set to "" to disable, and is not sent by compression bombs.
+    ///
+    /// Example usage: Set to "</body></html>" with the default prefix, or "}" when the prefix
+    /// is "{", so that parsers accept the output as one (very large) valid document.
+    #[serde(default = "default_generator_suffix")]
+    pub suffix: String,
+
+    /// Encodings to use for sending a pre-compressed "decompression bomb" instead of generated
+    /// data, in order of preference. Only used if the client accepts one of them in its
+    /// `Accept-Encoding` header. Empty to disable.
+    #[serde(default = "default_generator_compression_bomb")]
+    pub compression_bomb: Vec<ContentEncoding>,
+
+    /// What to do with new connections when `max_concurrent` generators are already producing
+    /// output. Waits forever by default.
+    #[serde(default = "default_generator_overflow_policy")]
+    pub overflow_policy: OverflowPolicy,
+
+    /// How many connections can wait for a generator at once when using the `wait` overflow
+    /// policy. Connections over this get a `503 Service Unavailable`. `0` means no limit.
+    #[serde(default = "default_generator_max_queued")]
+    pub max_queued: usize,
+}
+
+/// What to do with a connection that cannot get a generator, because `generator.max_concurrent`
+/// is reached.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "name", content = "data")]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OverflowPolicy {
+    /// Wait for a generator for at most this many seconds, and then respond with
+    /// `503 Service Unavailable`. `0` means waiting forever.
+    Wait(u64),
+    /// Respond with `503 Service Unavailable` right away, with a `Retry-After` header set to
+    /// this many seconds.
+    ServiceUnavailable(u64),
+    /// Respond with the contents of a file, once.
+    Static(PathBuf),
+    /// Send this many bytes per second, without using a generator.
+    Tarpit(usize),
+}
+
+impl fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wait(0) => write!(f, "wait forever"),
+            Self::Wait(timeout) => write!(f, "wait for at most {timeout} s"),
+            Self::ServiceUnavailable(retry_after) => {
+                write!(f, "service unavailable, retry after {retry_after} s")
+            }
+            Self::Static(pb) => write!(f, "static response from '{}'", pb.to_string_lossy()),
+            Self::Tarpit(bytes_per_second) => write!(f, "tarpit at {bytes_per_second} B/s"),
+        }
+    }
+}
+
+/// An encoding that can be used in a `Content-Encoding` header.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub(crate) enum ContentEncoding {
+    #[serde(rename = "gzip")]
+    Gzip,
+    #[serde(rename = "deflate")]
+    Deflate,
+    #[serde(rename = "br")]
+    Brotli,
+}
+
+impl ContentEncoding {
+    /// The name used for this encoding in HTTP headers.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+            Self::Brotli => "br",
+        }
+    }
+}
+
+impl fmt::Display for ContentEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
 }
 
 // While one could argue being able to pass strings in data as well is nicer, we quickly run into the
@@ -178,6 +785,125 @@ pub(crate) enum GeneratorType {
     /// Markov chain that also contains a path to the text to be used for generation
     MarkovChain(PathBuf),
     Static(PathBuf),
+    /// Text with links to more pages, where the data is the URI path that links point to. That
+    /// path must be served by `pandoras_pot` as well, for example using `/maze/{*rest}`.
+    LinkMaze(String),
+    /// An endless JSON array of records. It opens and closes the array itself, so `prefix` and
+    /// `suffix` must be empty.
+    Json,
+    /// An endless sitemap or news feed, with links to more pages. It starts and ends the document
+    /// itself, so `prefix` and `suffix` must be empty.
+    Feed(FeedConfig),
+    /// HTML with varied structure, using text from another generator.
+    Html(HtmlConfig),
+    /// Fake secrets, where each secret contains a canary that is logged when it is sent.
+    /// `prefix` and `suffix` must be empty, as the files start and end like real ones.
+    Secrets(SecretsFormat),
+}
+
+impl GeneratorType {
+    /// The `Content-Type` that output of this generator must be sent with, used instead of
+    /// `http.content_type` unless a route sets its own.
+    pub fn content_type(&self) -> Option<&'static str> {
+        match self {
+            Self::Json => Some("application/json"),
+            Self::Feed(feed) => Some(feed.format.content_type()),
+            Self::Secrets(_) => Some("text/plain; charset=utf-8"),
+            _ => None,
+        }
+    }
+}
+
+/// Settings of a [`GeneratorType::Feed`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct FeedConfig {
+    pub format: FeedFormat,
+    /// URI path that links point to. That path must be served by `pandoras_pot` as well, for
+    /// example using `/maze/{*rest}`.
+    pub links: String,
+    /// Text file that titles and descriptions are generated from using a Markov chain. Random
+    /// words are used if not set.
+    #[serde(default)]
+    pub markov_chain: Option<PathBuf>,
+}
+
+/// Settings of a [`GeneratorType::Html`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct HtmlConfig {
+    #[serde(default)]
+    pub text: TextSource,
+}
+
+/// Where a [`GeneratorType::Html`] gets the text between its tags from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "name", content = "data")]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TextSource {
+    /// Random words.
+    #[default]
+    Random,
+    /// A Markov chain made from the text in a file.
+    MarkovChain(PathBuf),
+    /// Words from a file, in order but starting anywhere.
+    Static(PathBuf),
+}
+
+/// The kind of file a [`GeneratorType::Secrets`] generates.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SecretsFormat {
+    /// `KEY=value` lines of a `.env` file.
+    Dotenv,
+    /// Sections of a `.git/config` file, with credentials in remote URLs.
+    GitConfig,
+    /// Profiles of an AWS `credentials` file.
+    AwsCredentials,
+    /// PEM blocks of private keys, like an `id_rsa` file.
+    PrivateKey,
+}
+
+impl SecretsFormat {
+    /// Name of this format, as used in the config.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Dotenv => "dotenv",
+            Self::GitConfig => "git_config",
+            Self::AwsCredentials => "aws_credentials",
+            Self::PrivateKey => "private_key",
+        }
+    }
+}
+
+/// The kind of document a [`GeneratorType::Feed`] generates.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FeedFormat {
+    /// `<url>` entries of a `sitemap.xml`.
+    Sitemap,
+    /// `<item>`s of an RSS 2.0 feed.
+    Rss,
+    /// `<entry>`s of an Atom feed.
+    Atom,
+}
+
+impl FeedFormat {
+    /// Name of this format, as used in the config.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sitemap => "sitemap",
+            Self::Rss => "rss",
+            Self::Atom => "atom",
+        }
+    }
+
+    /// The `Content-Type` documents of this format are sent with.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Sitemap => "application/xml; charset=utf-8",
+            Self::Rss => "application/rss+xml; charset=utf-8",
+            Self::Atom => "application/atom+xml; charset=utf-8",
+        }
+    }
 }
 
 impl fmt::Display for GeneratorType {
@@ -196,6 +922,36 @@ impl fmt::Display for GeneratorType {
                 "static generator with '{}' as data source",
                 pb.to_string_lossy()
             ),
+            Self::LinkMaze(prefix) => {
+                write!(f, "link maze generator with links under '{prefix}'")
+            }
+            Self::Json => write!(f, "JSON generator"),
+            Self::Feed(feed) => {
+                write!(
+                    f,
+                    "{} feed generator with links under '{}'",
+                    feed.format.as_str(),
+                    feed.links
+                )?;
+                match &feed.markov_chain {
+                    Some(pb) => write!(f, " and '{}' as data source", pb.to_string_lossy()),
+                    None => Ok(()),
+                }
+            }
+            Self::Html(html) => match &html.text {
+                TextSource::Random => write!(f, "HTML generator with random words"),
+                TextSource::MarkovChain(pb) => write!(
+                    f,
+                    "HTML generator with a Markov chain using '{}' as data source",
+                    pb.to_string_lossy()
+                ),
+                TextSource::Static(pb) => write!(
+                    f,
+                    "HTML generator with words from '{}'",
+                    pb.to_string_lossy()
+                ),
+            },
+            Self::Secrets(format) => write!(f, "fake secrets generator ({})", format.as_str()),
         }
     }
 }
@@ -215,6 +971,7 @@ impl Default for GeneratorConfig {
 }
 
 impl GeneratorConfig {
+    /// Creates a new config. Settings not provided use their default values.
     pub fn new(
         chunk_size: usize,
         generator_type: GeneratorType,
@@ -232,6 +989,10 @@ impl GeneratorConfig {
             size_limit,
             chunk_buffer,
             prefix,
+            suffix: default_generator_suffix(),
+            compression_bomb: default_generator_compression_bomb(),
+            overflow_policy: default_generator_overflow_policy(),
+            max_queued: default_generator_max_queued(),
         }
     }
 
@@ -276,6 +1037,113 @@ fn default_generator_prefix() -> String {
     "<!DOCTYPE html><html><body>".to_string()
 }
 
+fn default_generator_suffix() -> String {
+    String::new()
+}
+
+fn default_generator_compression_bomb() -> Vec<ContentEncoding> {
+    vec![]
+}
+
+const fn default_generator_overflow_policy() -> OverflowPolicy {
+    OverflowPolicy::Wait(0)
+}
+
+const fn default_generator_max_queued() -> usize {
+    100
+}
+
+/// A route with its own settings, overriding the global ones in [`HttpConfig`] and
+/// [`GeneratorConfig`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct RouteConfig {
+    /// Path pattern to serve, such as `/.env` or `/api/{*rest}`. Overrides the same path in
+    /// `http.routes`.
+    pub path: String,
+
+    /// Overrides `generator.type`.
+    #[serde(default, rename = "type")]
+    pub generator_type: Option<GeneratorType>,
+
+    /// Overrides `generator.prefix`.
+    #[serde(default)]
+    pub prefix: Option<String>,
+
+    /// Overrides `generator.suffix`.
+    #[serde(default)]
+    pub suffix: Option<String>,
+
+    /// Overrides `http.content_type`.
+    #[serde(default)]
+    pub content_type: Option<String>,
+
+    /// Overrides `generator.time_limit`.
+    #[serde(default)]
+    pub time_limit: Option<u64>,
+
+    /// Overrides `generator.size_limit`.
+    #[serde(default)]
+    pub size_limit: Option<usize>,
+
+    /// Overrides `generator.max_concurrent`. Note that each route has its own limit.
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+
+    /// Overrides `generator.compression_bomb`.
+    #[serde(default)]
+    pub compression_bomb: Option<Vec<ContentEncoding>>,
+
+    /// Overrides `generator.overflow_policy`.
+    #[serde(default)]
+    pub overflow_policy: Option<OverflowPolicy>,
+}
+
+impl RouteConfig {
+    /// The generator configuration for this route, using `defaults` for anything not
+    /// overridden.
+    pub fn generator_config(&self, defaults: &GeneratorConfig) -> GeneratorConfig {
+        GeneratorConfig {
+            generator_type: self
+                .generator_type
+                .clone()
+                .unwrap_or_else(|| defaults.generator_type.clone()),
+            prefix: self
+                .prefix
+                .clone()
+                .unwrap_or_else(|| defaults.prefix.clone()),
+            suffix: self
+                .suffix
+                .clone()
+                .unwrap_or_else(|| defaults.suffix.clone()),
+            time_limit: self.time_limit.unwrap_or(defaults.time_limit),
+            size_limit: self.size_limit.unwrap_or(defaults.size_limit),
+            max_concurrent: self.max_concurrent.unwrap_or(defaults.max_concurrent),
+            compression_bomb: self
+                .compression_bomb
+                .clone()
+                .unwrap_or_else(|| defaults.compression_bomb.clone()),
+            overflow_policy: self
+                .overflow_policy
+                .clone()
+                .unwrap_or_else(|| defaults.overflow_policy.clone()),
+            ..defaults.clone()
+        }
+    }
+
+    /// The `Content-Type` for this route when using `generator_type`, using `defaults` if
+    /// neither overrides it.
+    pub fn content_type<'a>(
+        &'a self,
+        generator_type: &GeneratorType,
+        defaults: &'a HttpConfig,
+    ) -> &'a str {
+        self.content_type
+            .as_deref()
+            .or(generator_type.content_type())
+            .unwrap_or(defaults.content_type.as_str())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub(crate) struct LoggingConfig {
     /// Output file for logs.
@@ -318,7 +1186,52 @@ const fn default_logging_no_stdout() -> bool {
 
 #[cfg(test)]
 mod tests {
-    use super::Config;
+    use std::{io::Write, path::PathBuf};
+
+    use tempfile::NamedTempFile;
+
+    use crate::error_code;
+
+    use super::{
+        Config, ConfigError, ContentEncoding, FeedConfig, FeedFormat, GeneratorType, HtmlConfig,
+        LimitAction, ListenAddress, ListenerConfig, OverflowPolicy, SecretsFormat, TextSource,
+    };
+
+    #[test]
+    fn config_errors() {
+        let path = PathBuf::from("/this/config/does/not/exist.toml");
+        let error = Config::from_path(&path, false).unwrap_err();
+        assert!(matches!(&error, ConfigError::NotFound(p) if *p == path));
+        assert_eq!(error.exit_code(), error_code::CONFIG_NOT_FOUND);
+
+        let mut tmpfile = NamedTempFile::new().unwrap();
+        write!(
+            tmpfile,
+            "# Comment\n[http]\nport = \"8080\"\n\n[generator]\nchunk_size = \"big\"\n"
+        )
+        .unwrap();
+        let error = Config::from_path(tmpfile.path(), false).unwrap_err();
+        assert_eq!(error.exit_code(), error_code::UNPARSEABLE_CONFIG);
+        let ConfigError::Parse(parse_error) = &error else {
+            panic!("expected a parse error, got {error:?}");
+        };
+        assert_eq!(parse_error.line, Some(6));
+        assert_eq!(parse_error.column, Some(14));
+        assert_eq!(parse_error.key.as_deref(), Some("generator.chunk_size"));
+        let message = error.to_string();
+        assert!(message.contains("`generator.chunk_size`"), "{message}");
+        assert!(message.contains("(line 6, column 14)"), "{message}");
+
+        // Broken TOML has no key to blame
+        let mut tmpfile = NamedTempFile::new().unwrap();
+        writeln!(tmpfile, "[http").unwrap();
+        let ConfigError::Parse(parse_error) = Config::from_path(tmpfile.path(), false).unwrap_err()
+        else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(parse_error.line, Some(1));
+        assert_eq!(parse_error.key, None);
+    }
 
     #[test]
     fn deserialize_incomplete_config() {
@@ -336,6 +1249,53 @@ mod tests {
         toml::from_str::<Config>(toml_str).unwrap();
     }
 
+    #[test]
+    fn unknown_keys() {
+        let mut tmpfile = NamedTempFile::new().unwrap();
+        let toml_str = r#"
+            [http]
+            prot = "7796"
+
+            [http.tls]
+            cert_path = "/cert.pem"
+            key_path = "/key.pem"
+            reload = 60
+
+            [generator]
+            min_chunk_size = 8000
+
+            [[routes]]
+            path = "/.env"
+            chunk_size = 8000
+        "#;
+        write!(tmpfile, "{toml_str}").unwrap();
+
+        let (_, unknown_keys) = Config::from_path(tmpfile.path(), false).unwrap();
+        let paths: Vec<&str> = unknown_keys.iter().map(|k| k.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "generator.min_chunk_size",
+                "http.prot",
+                "http.tls.reload",
+                "routes[0].chunk_size"
+            ]
+        );
+        assert_eq!(unknown_keys[1].hint, None);
+        assert!(unknown_keys[0]
+            .hint
+            .unwrap()
+            .contains("generator.chunk_size"));
+
+        let error = Config::from_path(tmpfile.path(), true).unwrap_err();
+        assert_eq!(error.exit_code(), error_code::UNKNOWN_CONFIG_KEYS);
+        assert!(matches!(error, ConfigError::UnknownKeys(_, keys) if keys == unknown_keys));
+
+        // Configs can be strict by themselves
+        std::fs::write(tmpfile.path(), format!("strict = true\n{toml_str}")).unwrap();
+        assert!(Config::from_path(tmpfile.path(), false).is_err());
+    }
+
     #[test]
     fn deserialize_empty_config() {
         toml::from_str::<Config>("").unwrap();
@@ -359,6 +1319,262 @@ mod tests {
         toml::from_str::<Config>(toml_str).unwrap();
     }
 
+    #[test]
+    fn deserialize_link_maze_generator_config() {
+        let toml_str = r#"
+            [generator]
+            type = { name = "link_maze", data = "/maze" }
+        "#;
+        let config = toml::from_str::<Config>(toml_str).unwrap();
+        assert_eq!(
+            config.generator.generator_type,
+            GeneratorType::LinkMaze("/maze".to_string())
+        );
+    }
+
+    #[test]
+    fn deserialize_json_generator_config() {
+        let toml_str = r#"
+            [generator]
+            type = { name = "json" }
+        "#;
+        let config = toml::from_str::<Config>(toml_str).unwrap();
+        assert_eq!(config.generator.generator_type, GeneratorType::Json);
+    }
+
+    #[test]
+    fn deserialize_feed_generator_config() {
+        let toml_str = r#"
+            [generator.type]
+            name = "feed"
+            data = { format = "rss", links = "/maze", markov_chain = "/some/text" }
+
+            [[routes]]
+            path = "/sitemap.xml"
+            type = { name = "feed", data = { format = "sitemap", links = "/maze" } }
+
+            [[routes]]
+            path = "/atom.xml"
+            type = { name = "feed", data = { format = "atom", links = "/maze" } }
+            content_type = "text/xml"
+        "#;
+        let config = toml::from_str::<Config>(toml_str).unwrap();
+        assert_eq!(
+            config.generator.generator_type,
+            GeneratorType::Feed(FeedConfig {
+                format: FeedFormat::Rss,
+                links: "/maze".to_string(),
+                markov_chain: Some(PathBuf::from("/some/text")),
+            })
+        );
+
+        // The feed decides the content type, unless the route sets one
+        let sitemap = &config.routes[0];
+        let generator = sitemap.generator_config(&config.generator);
+        assert_eq!(
+            sitemap.content_type(&generator.generator_type, &config.http),
+            "application/xml; charset=utf-8"
+        );
+        let atom = &config.routes[1];
+        let generator = atom.generator_config(&config.generator);
+        assert_eq!(
+            atom.content_type(&generator.generator_type, &config.http),
+            "text/xml"
+        );
+    }
+
+    #[test]
+    fn deserialize_html_generator_config() {
+        let toml_str = r#"
+            [generator]
+            type = { name = "html", data = {} }
+
+            [[routes]]
+            path = "/blog/{*rest}"
+            [routes.type]
+            name = "html"
+            data = { text = { name = "markov_chain", data = "/some/text" } }
+        "#;
+        let config = toml::from_str::<Config>(toml_str).unwrap();
+        assert_eq!(
+            config.generator.generator_type,
+            GeneratorType::Html(HtmlConfig {
+                text: TextSource::Random
+            })
+        );
+        assert_eq!(
+            config.routes[0].generator_type,
+            Some(GeneratorType::Html(HtmlConfig {
+                text: TextSource::MarkovChain(PathBuf::from("/some/text"))
+            }))
+        );
+    }
+
+    #[test]
+    fn deserialize_secrets_generator_config() {
+        let toml_str = r#"
+            [[routes]]
+            path = "/.env"
+            type = { name = "secrets", data = "dotenv" }
+
+            [[routes]]
+            path = "/.git/config"
+            type = { name = "secrets", data = "git_config" }
+        "#;
+        let config = toml::from_str::<Config>(toml_str).unwrap();
+        let dotenv = &config.routes[0];
+        let generator = dotenv.generator_config(&config.generator);
+        assert_eq!(
+            generator.generator_type,
+            GeneratorType::Secrets(SecretsFormat::Dotenv)
+        );
+        assert_eq!(
+            dotenv.content_type(&generator.generator_type, &config.http),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            config.routes[1].generator_type,
+            Some(GeneratorType::Secrets(SecretsFormat::GitConfig))
+        );
+    }
+
+    #[test]
+    fn deserialize_overflow_policy_config() {
+        let toml_str = r#"
+            [generator]
+            overflow_policy = { name = "service_unavailable", data = 60 }
+            max_queued = 0
+        "#;
+        let config = toml::from_str::<Config>(toml_str).unwrap();
+        assert_eq!(
+            config.generator.overflow_policy,
+            OverflowPolicy::ServiceUnavailable(60)
+        );
+        assert_eq!(config.generator.max_queued, 0);
+
+        let toml_str = r#"
+            [generator]
+            overflow_policy = { name = "static", data = "/some/page.html" }
+        "#;
+        let config = toml::from_str::<Config>(toml_str).unwrap();
+        assert_eq!(
+            config.generator.overflow_policy,
+            OverflowPolicy::Static(PathBuf::from("/some/page.html"))
+        );
+    }
+
+    #[test]
+    fn deserialize_tls_config() {
+        let toml_str = r#"
+            [http.tls]
+            cert_path = "/etc/pandoras_pot/cert.pem"
+            key_path = "/etc/pandoras_pot/key.pem"
+        "#;
+        let config = toml::from_str::<Config>(toml_str).unwrap();
+        let tls = config.http.tls.unwrap();
+        assert_eq!(tls.cert_path, PathBuf::from("/etc/pandoras_pot/cert.pem"));
+        assert_eq!(tls.reload_interval, 60 * 60);
+    }
+
+    #[test]
+    fn deserialize_trusted_proxies_config() {
+        let config = toml::from_str::<Config>("").unwrap();
+        assert_eq!(config.http.trusted_proxies.len(), 2);
+
+        let toml_str = r#"
+            [http]
+            trusted_proxies = ["10.0.0.0/8", "2001:db8::/32"]
+        "#;
+        let config = toml::from_str::<Config>(toml_str).unwrap();
+        assert_eq!(
+            config.http.trusted_proxies,
+            vec![
+                "10.0.0.0/8".parse().unwrap(),
+                "2001:db8::/32".parse().unwrap()
+            ]
+        );
+
+        let toml_str = r#"
+            [http]
+            trusted_proxies = ["10.0.0.1"]
+        "#;
+        assert!(toml::from_str::<Config>(toml_str).is_err());
+    }
+
+    #[test]
+    fn deserialize_client_limits_config() {
+        let toml_str = r#"
+            [http.client_limits]
+            rate_limit = 10
+            max_concurrent = 2
+            max_concurrent_action = "queue"
+        "#;
+        let config = toml::from_str::<Config>(toml_str).unwrap();
+        let limits = &config.http.client_limits;
+        assert_eq!(limits.rate_limit, 10);
+        assert_eq!(limits.rate_limit_period, 60);
+        assert_eq!(limits.rate_limit_action, LimitAction::TooManyRequests);
+        assert_eq!(limits.max_concurrent, 2);
+        assert_eq!(limits.max_concurrent_action, LimitAction::Queue);
+        assert_eq!(limits.max_queued, 10);
+        assert_eq!(limits.max_clients, 10_000);
+    }
+
+    #[test]
+    fn deserialize_listen_config() {
+        let toml_str = r#"
+            [http]
+            listen = [
+                "127.0.0.1:8080",
+                { address = "[::]:8443", routes = ["/wp-login.php"], proxy_protocol = true },
+                { address = "unix:/run/pandoras_pot.sock", mode = 0o660, group = "caddy" },
+            ]
+            health_listen = ["unix:/run/pandoras_pot_health.sock"]
+        "#;
+        let config = toml::from_str::<Config>(toml_str).unwrap();
+        let listen = &config.http.listen;
+        assert_eq!(
+            listen[0],
+            ListenerConfig::new(ListenAddress::Tcp("127.0.0.1:8080".parse().unwrap()))
+        );
+        assert_eq!(listen[1].address.to_string(), "[::]:8443");
+        assert_eq!(listen[1].routes, Some(vec!["/wp-login.php".to_string()]));
+        assert!(listen[1].proxy_protocol);
+        assert!(!listen[2].proxy_protocol);
+        assert_eq!(
+            listen[2].address,
+            ListenAddress::Unix(PathBuf::from("/run/pandoras_pot.sock"))
+        );
+        assert_eq!(listen[2].mode, Some(0o660));
+        assert_eq!(listen[2].group.as_deref(), Some("caddy"));
+        assert_eq!(
+            config.http.health_listen[0].address.to_string(),
+            "unix:/run/pandoras_pot_health.sock"
+        );
+
+        // Should survive a round trip
+        let serialized = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<Config>(&serialized).unwrap(), config);
+
+        for (bad, expected) in [
+            (r#"listen = ["8080"]"#, "invalid listen address"),
+            (r#"listen = ["localhost:8080"]"#, "invalid listen address"),
+            (r#"listen = ["unix:"]"#, "path is empty"),
+            (
+                r#"listen = [{ address = "[::]:80", mode = 0o600 }]"#,
+                "only be set for unix sockets",
+            ),
+            (
+                r#"listen = [{ address = "unix:/a.sock", mode = 0o10000 }]"#,
+                "invalid mode",
+            ),
+        ] {
+            let toml_str = format!("[http]\n{bad}");
+            let err = toml::from_str::<Config>(&toml_str).unwrap_err();
+            assert!(err.message().contains(expected), "{err}");
+        }
+    }
+
     #[test]
     fn deserialize_config_1() {
         let toml_str = r#"
@@ -419,4 +1635,62 @@ mod tests {
         "#;
         toml::from_str::<Config>(toml_str).unwrap();
     }
+
+    #[test]
+    fn deserialize_route_config() {
+        let toml_str = r#"
+            [generator]
+            prefix = "<html>"
+            suffix = "</html>"
+            time_limit = 60
+
+            [[routes]]
+            path = "/.env"
+            type = { name = "static", data = "/some/dotenv" }
+            prefix = ""
+            suffix = ""
+            content_type = "text/plain"
+            size_limit = 1000
+
+            [[routes]]
+            path = "/api/{*rest}"
+            max_concurrent = 5
+            compression_bomb = ["br", "gzip"]
+            overflow_policy = { name = "tarpit", data = 4 }
+        "#;
+        let config = toml::from_str::<Config>(toml_str).unwrap();
+        assert_eq!(config.routes.len(), 2);
+
+        let dotenv = &config.routes[0];
+        let generator = dotenv.generator_config(&config.generator);
+        assert_eq!(
+            generator.generator_type,
+            GeneratorType::Static(PathBuf::from("/some/dotenv"))
+        );
+        assert_eq!(generator.prefix, "");
+        assert_eq!(generator.suffix, "");
+        assert_eq!(generator.time_limit, 60);
+        assert_eq!(generator.size_limit, 1000);
+        assert_eq!(generator.overflow_policy, OverflowPolicy::Wait(0));
+        assert_eq!(
+            dotenv.content_type(&generator.generator_type, &config.http),
+            "text/plain"
+        );
+
+        let api = &config.routes[1];
+        let generator = api.generator_config(&config.generator);
+        assert_eq!(generator.generator_type, config.generator.generator_type);
+        assert_eq!(generator.prefix, "<html>");
+        assert_eq!(generator.suffix, "</html>");
+        assert_eq!(generator.max_concurrent(), 5);
+        assert_eq!(
+            generator.compression_bomb,
+            vec![ContentEncoding::Brotli, ContentEncoding::Gzip]
+        );
+        assert_eq!(generator.overflow_policy, OverflowPolicy::Tarpit(4));
+        assert_eq!(
+            api.content_type(&generator.generator_type, &config.http),
+            config.http.content_type
+        );
+    }
 }
@@ -11,6 +11,13 @@ pub(crate) const UNPARSEABLE_CONFIG: i32 = 10;
 pub(crate) const BAD_CONFIG: i32 = 11;
 pub(crate) const BAD_CONTENT_TYPE: i32 = 12;
 
+/// The given config file does not exist.
+pub(crate) const CONFIG_NOT_FOUND: i32 = 13;
+/// The config file exists, but could not be read.
+pub(crate) const CANNOT_READ_CONFIG: i32 = 14;
+/// The config has unknown keys, and strict checking is enabled.
+pub(crate) const UNKNOWN_CONFIG_KEYS: i32 = 15;
+
 /// The desired log file path could not be opened.
 pub(crate) const CANNOT_OPEN_LOG_FILE: i32 = 20;
 
@@ -18,3 +25,15 @@ pub(crate) const CANNOT_OPEN_LOG_FILE: i32 = 20;
 pub(crate) const CANNOT_READ_GENERATOR_DATA_FILE: i32 = 30;
 pub(crate) const GENERATOR_CHUNK_SIZE_TOO_SMALL: i32 = 31;
 pub(crate) const GENERATOR_CHUNK_BUFFER_TOO_SMALL: i32 = 32;
+/// A configured compression bomb could not be created.
+pub(crate) const CANNOT_CREATE_COMPRESSION_BOMB: i32 = 33;
+
+/// TLS was configured, but `pandoras_pot` was built without the `tls` feature.
+#[cfg_attr(feature = "tls", allow(dead_code))]
+pub(crate) const TLS_NOT_SUPPORTED: i32 = 40;
+/// The configured TLS certificate or key could not be loaded.
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+pub(crate) const CANNOT_LOAD_TLS_CERTIFICATE: i32 = 41;
+
+/// One of the configured addresses could not be listened on.
+pub(crate) const CANNOT_BIND: i32 = 50;
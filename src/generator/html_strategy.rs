@@ -0,0 +1,495 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use rand::{seq::IndexedRandom, Rng};
+use tokio::sync::mpsc;
+use tracing::instrument;
+
+use crate::{
+    config::{HtmlConfig, TextSource},
+    request_context::RequestContext,
+};
+
+use super::{
+    feed_strategy::push_escaped, link_maze_strategy::push_word, markov_strategy::MarkovChain,
+    static_strategy::Static, GeneratorStrategy,
+};
+
+/// How deep containers such as `<div>` may be nested in each top-level element.
+const MAX_DEPTH: u32 = 3;
+
+/// Elements that other elements are nested in.
+const CONTAINERS: &[&str] = &[
+    "div", "div", "section", "article", "aside", "nav", "header", "footer", "main",
+];
+
+/// Where the text between tags comes from.
+#[derive(Clone, Debug)]
+enum Text {
+    Random,
+    MarkovChain(MarkovChain),
+    /// Every word of a file.
+    Static(Arc<[String]>),
+}
+
+/// Generates HTML with headings, nested containers, lists, tables, forms, images, scripts, styles
+/// and comments, filled with text from another generator.
+///
+/// Each chunk consists of whole top-level elements, so tags are always balanced between chunks.
+/// Like [`MarkovChain`], chunks may be a bit larger than `chunk_size`.
+#[derive(Clone, Debug)]
+pub(crate) struct Html {
+    chunk_size: usize,
+    text: Text,
+}
+
+impl Html {
+    /// Creates a generator, reading the file used for text if there is one.
+    ///
+    /// Returns an exit code if it cannot be read.
+    pub fn new(chunk_size: usize, config: &HtmlConfig) -> Result<Self, i32> {
+        let text = match &config.text {
+            TextSource::Random => Text::Random,
+            TextSource::MarkovChain(input) => {
+                Text::MarkovChain(MarkovChain::new(chunk_size, input)?)
+            }
+            TextSource::Static(input) => {
+                let data = Static::new(input)?.data();
+                let words = String::from_utf8_lossy(&data)
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect();
+                Text::Static(words)
+            }
+        };
+        Ok(Self { chunk_size, text })
+    }
+
+    /// Pushes about `words` words of escaped text to `s`.
+    fn push_text<R: Rng>(&self, rng: &mut R, words: usize, s: &mut String) {
+        match &self.text {
+            // Tokens include whitespace, so there are about two per word
+            Text::MarkovChain(chain) => {
+                push_escaped(&chain.text(rng, words * 2), s);
+                return;
+            }
+            Text::Static(all_words) if !all_words.is_empty() => {
+                let start = rng.random_range(0..all_words.len());
+                for (i, word) in all_words.iter().cycle().skip(start).take(words).enumerate() {
+                    if i > 0 {
+                        s.push(' ');
+                    }
+                    push_escaped(word, s);
+                }
+                return;
+            }
+            _ => {}
+        }
+        for i in 0..words {
+            if i > 0 {
+                s.push(' ');
+            }
+            push_word(rng, s);
+        }
+    }
+
+    /// Pushes a container with other elements nested at most `depth` more levels.
+    fn push_container<R: Rng>(&self, rng: &mut R, depth: u32, s: &mut String) {
+        let tag = *CONTAINERS.choose(rng).expect("not empty");
+        s.push('<');
+        s.push_str(tag);
+        push_class(rng, s);
+        s.push_str(">\n");
+        for _ in 0..rng.random_range(1..=4) {
+            self.push_element(rng, depth, s);
+        }
+        s.push_str("</");
+        s.push_str(tag);
+        s.push_str(">\n");
+    }
+
+    /// Pushes a random element, which is a container unless `depth` is `0`.
+    fn push_element<R: Rng>(&self, rng: &mut R, depth: u32, s: &mut String) {
+        let kinds = if depth == 0 { 11 } else { 14 };
+        match rng.random_range(0..kinds) {
+            0 => self.push_heading(rng, s),
+            1..=3 => self.push_paragraph(rng, s),
+            4 => self.push_list(rng, 1, s),
+            5 => self.push_table(rng, s),
+            6 => self.push_form(rng, s),
+            7 => self.push_figure(rng, s),
+            8 => push_script(rng, s),
+            9 => push_style(rng, s),
+            10 => {
+                // Words only, since comments cannot contain just anything
+                s.push_str("<!-- ");
+                for _ in 0..rng.random_range(2..=8) {
+                    push_word(rng, s);
+                    s.push(' ');
+                }
+                s.push_str("-->\n");
+            }
+            _ => self.push_container(rng, depth - 1, s),
+        }
+    }
+
+    fn push_heading<R: Rng>(&self, rng: &mut R, s: &mut String) {
+        let level = rng.random_range(1..=6);
+        s.push_str(&format!("<h{level}"));
+        push_class(rng, s);
+        s.push('>');
+        let words = rng.random_range(2..=8);
+        self.push_text(rng, words, s);
+        s.push_str(&format!("</h{level}>\n"));
+    }
+
+    /// Pushes a paragraph of text, with some links and emphasis.
+    fn push_paragraph<R: Rng>(&self, rng: &mut R, s: &mut String) {
+        s.push_str("<p>");
+        for i in 0..rng.random_range(1..=5) {
+            if i > 0 {
+                s.push(' ');
+            }
+            let words = rng.random_range(3..=30);
+            match rng.random_range(0..8) {
+                0 => {
+                    s.push_str("<a href=\"");
+                    push_path(rng, s);
+                    s.push_str("\">");
+                    self.push_text(rng, words.min(5), s);
+                    s.push_str("</a>");
+                }
+                1 => {
+                    let tag = *["em", "strong", "code", "span"]
+                        .choose(rng)
+                        .expect("not empty");
+                    s.push_str(&format!("<{tag}>"));
+                    self.push_text(rng, words.min(5), s);
+                    s.push_str(&format!("</{tag}>"));
+                }
+                2 => s.push_str("<br>"),
+                _ => self.push_text(rng, words, s),
+            }
+        }
+        s.push_str("</p>\n");
+    }
+
+    /// Pushes a list of items, where items may have lists of their own if `depth` is above `0`.
+    fn push_list<R: Rng>(&self, rng: &mut R, depth: u32, s: &mut String) {
+        let tag = if rng.random_bool(0.7) { "ul" } else { "ol" };
+        s.push_str(&format!("<{tag}"));
+        push_class(rng, s);
+        s.push_str(">\n");
+        for _ in 0..rng.random_range(2..=6) {
+            s.push_str("<li>");
+            if rng.random_bool(0.4) {
+                s.push_str("<a href=\"");
+                push_path(rng, s);
+                s.push_str("\">");
+                let words = rng.random_range(1..=4);
+                self.push_text(rng, words, s);
+                s.push_str("</a>");
+            } else {
+                let words = rng.random_range(2..=12);
+                self.push_text(rng, words, s);
+            }
+            if depth > 0 && rng.random_ratio(1, 6) {
+                s.push('\n');
+                self.push_list(rng, depth - 1, s);
+            }
+            s.push_str("</li>\n");
+        }
+        s.push_str(&format!("</{tag}>\n"));
+    }
+
+    fn push_table<R: Rng>(&self, rng: &mut R, s: &mut String) {
+        let columns = rng.random_range(2..=6);
+        s.push_str("<table");
+        push_class(rng, s);
+        s.push_str(">\n<thead><tr>");
+        for _ in 0..columns {
+            s.push_str("<th>");
+            let words = rng.random_range(1..=2);
+            self.push_text(rng, words, s);
+            s.push_str("</th>");
+        }
+        s.push_str("</tr></thead>\n<tbody>\n");
+        for _ in 0..rng.random_range(1..=10) {
+            s.push_str("<tr>");
+            for _ in 0..columns {
+                s.push_str("<td>");
+                if rng.random_bool(0.5) {
+                    s.push_str(&format!("{:.2}", rng.random_range(0.0..10000.0_f64)));
+                } else {
+                    let words = rng.random_range(1..=4);
+                    self.push_text(rng, words, s);
+                }
+                s.push_str("</td>");
+            }
+            s.push_str("</tr>\n");
+        }
+        s.push_str("</tbody>\n</table>\n");
+    }
+
+    fn push_form<R: Rng>(&self, rng: &mut R, s: &mut String) {
+        s.push_str("<form action=\"");
+        push_path(rng, s);
+        s.push_str("\" method=\"post\"");
+        push_class(rng, s);
+        s.push_str(">\n");
+        for _ in 0..rng.random_range(1..=5) {
+            let mut name = String::new();
+            push_word(rng, &mut name);
+            s.push_str(&format!("<label for=\"{name}\">"));
+            let words = rng.random_range(1..=3);
+            self.push_text(rng, words, s);
+            s.push_str("</label>");
+            match rng.random_range(0..4) {
+                0 => s.push_str(&format!(
+                    "<textarea id=\"{name}\" name=\"{name}\"></textarea>\n"
+                )),
+                1 => {
+                    s.push_str(&format!("<select id=\"{name}\" name=\"{name}\">"));
+                    for _ in 0..rng.random_range(2..=5) {
+                        s.push_str("<option>");
+                        push_word(rng, s);
+                        s.push_str("</option>");
+                    }
+                    s.push_str("</select>\n");
+                }
+                _ => {
+                    let input_type = ["text", "email", "password", "search", "number"]
+                        .choose(rng)
+                        .expect("not empty");
+                    s.push_str(&format!(
+                        "<input type=\"{input_type}\" id=\"{name}\" name=\"{name}\">\n"
+                    ));
+                }
+            }
+        }
+        s.push_str("<input type=\"hidden\" name=\"csrf_token\" value=\"");
+        s.push_str(&format!("{:016x}", rng.random::<u64>()));
+        s.push_str("\">\n<button type=\"submit\">");
+        push_word(rng, s);
+        s.push_str("</button>\n</form>\n");
+    }
+
+    /// Pushes an image with a fake `src`, and a caption.
+    fn push_figure<R: Rng>(&self, rng: &mut R, s: &mut String) {
+        s.push_str("<figure><img src=\"/images/");
+        push_word(rng, s);
+        s.push('-');
+        push_word(rng, s);
+        s.push_str([".jpg", ".png", ".webp"].choose(rng).expect("not empty"));
+        s.push_str("\" alt=\"");
+        let words = rng.random_range(2..=6);
+        self.push_text(rng, words, s);
+        s.push_str(&format!(
+            "\" width=\"{}\" height=\"{}\"><figcaption>",
+            rng.random_range(100..=1920),
+            rng.random_range(100..=1080)
+        ));
+        let words = rng.random_range(3..=12);
+        self.push_text(rng, words, s);
+        s.push_str("</figcaption></figure>\n");
+    }
+}
+
+/// Pushes a `class` attribute with a few random class names, some of the time.
+fn push_class<R: Rng>(rng: &mut R, s: &mut String) {
+    if rng.random_bool(0.5) {
+        return;
+    }
+    s.push_str(" class=\"");
+    for i in 0..rng.random_range(1..=3) {
+        if i > 0 {
+            s.push(' ');
+        }
+        push_word(rng, s);
+    }
+    s.push('"');
+}
+
+/// Pushes a path to a random page.
+fn push_path<R: Rng>(rng: &mut R, s: &mut String) {
+    for _ in 0..rng.random_range(1..=3) {
+        s.push('/');
+        push_word(rng, s);
+    }
+    if rng.random_bool(0.5) {
+        s.push_str(".html");
+    }
+}
+
+/// Pushes a script of made up JavaScript. Words only, so it cannot end the script early.
+fn push_script<R: Rng>(rng: &mut R, s: &mut String) {
+    s.push_str("<script>\n");
+    for _ in 0..rng.random_range(1..=4) {
+        let mut name = String::new();
+        push_word(rng, &mut name);
+        let mut argument = String::new();
+        push_word(rng, &mut argument);
+        match rng.random_range(0..3) {
+            0 => s.push_str(&format!("var {name} = {};\n", rng.random_range(0..10000))),
+            1 => s.push_str(&format!(
+                "function {name}({argument}) {{ return {argument} * {}; }}\n",
+                rng.random_range(2..100)
+            )),
+            _ => s.push_str(&format!(
+                concat!(
+                    "document.querySelectorAll(\".{name}\")",
+                    ".forEach(function ({argument}) {{ {argument}.hidden = {hidden}; }});\n",
+                ),
+                name = name,
+                argument = argument,
+                hidden = rng.random_bool(0.5),
+            )),
+        }
+    }
+    s.push_str("</script>\n");
+}
+
+/// Pushes a style block with a few made up rules.
+fn push_style<R: Rng>(rng: &mut R, s: &mut String) {
+    s.push_str("<style>\n");
+    for _ in 0..rng.random_range(1..=4) {
+        s.push('.');
+        push_word(rng, s);
+        s.push_str(&format!(
+            " {{ margin: {}px {}px; color: #{:06x}; }}\n",
+            rng.random_range(0..40),
+            rng.random_range(0..40),
+            rng.random_range(0..0x0100_0000)
+        ));
+    }
+    s.push_str("</style>\n");
+}
+
+impl GeneratorStrategy for Html {
+    fn name(&self) -> &'static str {
+        "html"
+    }
+
+    #[instrument(name = "spawn_html", skip_all)]
+    fn start(self, tx: mpsc::Sender<Bytes>, context: &RequestContext) {
+        let mut rng = context.rng();
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            loop {
+                let mut result = String::with_capacity(self.chunk_size + 1000);
+                while result.len() < self.chunk_size {
+                    self.push_container(&mut rng, MAX_DEPTH, &mut result);
+                }
+
+                if tx.blocking_send(Bytes::from(result)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+    use tokio::sync::mpsc;
+
+    use crate::{
+        config::{HtmlConfig, TextSource},
+        generator::GeneratorStrategy,
+        request_context::RequestContext,
+    };
+
+    use super::Html;
+
+    /// Elements that are never closed.
+    const VOID_ELEMENTS: &[&str] = &["br", "img", "input"];
+
+    /// Asserts that every tag in `html` is closed, in the right order.
+    fn assert_balanced(html: &str) {
+        let mut open = Vec::new();
+        let mut rest = html;
+        while let Some(start) = rest.find('<') {
+            rest = &rest[start..];
+            if let Some(comment) = rest.strip_prefix("<!--") {
+                let end = comment.find("-->").expect("unclosed comment");
+                rest = &comment[end + 3..];
+                continue;
+            }
+            let end = rest.find('>').expect("unclosed tag");
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(open.pop(), Some(name), "misplaced </{name}>");
+                continue;
+            }
+            let name = tag.split(' ').next().unwrap();
+            assert!(!name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric()));
+            if name == "script" || name == "style" {
+                // Their content is not HTML
+                let end = rest.find(&format!("</{name}>")).expect("unclosed script");
+                rest = &rest[end..];
+            }
+            if !VOID_ELEMENTS.contains(&name) {
+                open.push(name);
+            }
+        }
+        assert_eq!(open, Vec::<&str>::new(), "unclosed tags");
+    }
+
+    async fn chunks(html: Html, n: usize) -> Vec<String> {
+        let (tx, mut rx) = mpsc::channel(1);
+        html.start(tx, &RequestContext::default());
+        let mut chunks = Vec::with_capacity(n);
+        for _ in 0..n {
+            let chunk = rx.recv().await.unwrap();
+            chunks.push(String::from_utf8(chunk.to_vec()).unwrap());
+        }
+        chunks
+    }
+
+    #[tokio::test]
+    async fn chunks_are_balanced() {
+        let html = Html::new(2000, &HtmlConfig::default()).unwrap();
+        let chunks = chunks(html, 20).await;
+        for chunk in &chunks {
+            assert!(chunk.len() >= 2000);
+            assert_balanced(chunk);
+        }
+
+        let all = chunks.concat();
+        for tag in [
+            "<h",
+            "<ul",
+            "<table",
+            "<form",
+            "<img src=\"/images/",
+            "<script>",
+            "<style>",
+            "<!--",
+        ] {
+            assert!(all.contains(tag), "no {tag} in output");
+        }
+    }
+
+    #[tokio::test]
+    async fn uses_text_source() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"fish & chips <b>are</b> great").unwrap();
+        for text in [
+            TextSource::Static(file.path().to_path_buf()),
+            TextSource::MarkovChain(file.path().to_path_buf()),
+        ] {
+            let html = Html::new(2000, &HtmlConfig { text }).unwrap();
+            for chunk in chunks(html, 5).await {
+                assert_balanced(&chunk);
+                assert!(chunk.contains("chips"), "text not used in '{chunk}'");
+                assert!(chunk.contains("&amp;"));
+                assert!(!chunk.contains("<b>"));
+            }
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use bytes::Bytes;
+use rand::{seq::IndexedRandom, Rng};
+use tokio::sync::mpsc;
+use tracing::instrument;
+
+use crate::request_context::RequestContext;
+
+use super::{link_maze_strategy::push_word, GeneratorStrategy};
+
+/// Keys that records are made of, so they look like something from a real API.
+const KEYS: &[&str] = &[
+    "id",
+    "uuid",
+    "name",
+    "username",
+    "email",
+    "password_hash",
+    "api_key",
+    "created_at",
+    "updated_at",
+    "active",
+    "role",
+    "score",
+    "balance",
+    "tags",
+    "address",
+    "description",
+    "metadata",
+    "parent",
+    "children",
+    "items",
+];
+
+/// How deep values may be nested in each record.
+const MAX_DEPTH: u32 = 3;
+
+/// Generates an endless JSON array of records, like `[{"id":1,"data":{...}},{"id":2,...}`. The
+/// opening bracket is part of the first chunk and every chunk ends with a whole record, so the
+/// output is a valid JSON array once the closing bracket is sent as the suffix.
+#[derive(Clone, Debug)]
+pub(crate) struct Json {
+    chunk_size: usize,
+}
+
+impl Json {
+    pub fn new(chunk_size: usize) -> Self {
+        Self { chunk_size }
+    }
+}
+
+/// Pushes `c` to `s`, escaped so it can be part of a JSON string.
+fn push_escaped(c: char, s: &mut String) {
+    match c {
+        '"' => s.push_str("\\\""),
+        '\\' => s.push_str("\\\\"),
+        '\n' => s.push_str("\\n"),
+        '\r' => s.push_str("\\r"),
+        '\t' => s.push_str("\\t"),
+        c if c.is_control() => s.push_str(&format!("\\u{:04x}", u32::from(c))),
+        c => s.push(c),
+    }
+}
+
+/// Pushes a JSON string of random words, sometimes with characters that must be escaped.
+fn push_string<R: Rng>(rng: &mut R, s: &mut String) {
+    s.push('"');
+    for i in 0..rng.random_range(1..=8) {
+        if i > 0 {
+            s.push(' ');
+        }
+        let mut word = String::new();
+        push_word(rng, &mut word);
+        word.chars().for_each(|c| push_escaped(c, s));
+        if rng.random_ratio(1, 10) {
+            let c = *['"', '\\', '\n', '\t', '\u{1}', 'é', '€', '😀']
+                .choose(rng)
+                .expect("not empty");
+            push_escaped(c, s);
+        }
+    }
+    s.push('"');
+}
+
+/// Pushes a random JSON value, nesting objects and arrays at most `depth` more levels.
+fn push_value<R: Rng>(rng: &mut R, depth: u32, s: &mut String) {
+    let kinds = if depth == 0 { 6 } else { 8 };
+    match rng.random_range(0..kinds) {
+        0 => s.push_str(&rng.random_range(0..1_000_000_u32).to_string()),
+        1 => s.push_str(&format!("{:.2}", rng.random_range(-1000.0..1000.0_f64))),
+        2 => s.push_str(&rng.random_bool(0.5).to_string()),
+        3 => s.push_str("null"),
+        4 | 5 => push_string(rng, s),
+        6 => push_object(rng, depth - 1, s),
+        _ => {
+            s.push('[');
+            for i in 0..rng.random_range(0..=4) {
+                if i > 0 {
+                    s.push(',');
+                }
+                push_value(rng, depth - 1, s);
+            }
+            s.push(']');
+        }
+    }
+}
+
+/// Pushes a JSON object with random keys from [`KEYS`].
+fn push_object<R: Rng>(rng: &mut R, depth: u32, s: &mut String) {
+    s.push('{');
+    let len = rng.random_range(1..=6);
+    for (i, key) in KEYS.choose_multiple(rng, len).enumerate() {
+        if i > 0 {
+            s.push(',');
+        }
+        s.push('"');
+        s.push_str(key);
+        s.push_str("\":");
+        push_value(rng, depth, s);
+    }
+    s.push('}');
+}
+
+impl GeneratorStrategy for Json {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    // An HTML prefix would make the output invalid JSON
+    fn prepend_prefix(&self) -> bool {
+        false
+    }
+
+    fn suffix(&self) -> Option<&'static str> {
+        Some("]")
+    }
+
+    #[instrument(name = "spawn_json", skip_all)]
+    fn start(self, tx: mpsc::Sender<Bytes>, context: &RequestContext) {
+        let mut rng = context.rng();
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let mut id = 0_u64;
+            let mut result = String::with_capacity(self.chunk_size + 1000);
+            result.push('[');
+
+            loop {
+                while result.len() < self.chunk_size {
+                    // The first record of the stream is the only one without a comma before it
+                    if id > 0 {
+                        result.push(',');
+                    }
+                    id += 1;
+                    result.push_str(&format!("{{\"id\":{id},\"data\":"));
+                    push_object(&mut rng, MAX_DEPTH, &mut result);
+                    result.push('}');
+                }
+
+                let chunk =
+                    std::mem::replace(&mut result, String::with_capacity(self.chunk_size + 1000));
+                if tx.blocking_send(Bytes::from(chunk)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+    use tokio::sync::mpsc;
+
+    use crate::{
+        config::GeneratorConfig,
+        generator::{Generator, GeneratorStrategy},
+        metrics::EndReason,
+        request_context::RequestContext,
+    };
+
+    use super::{push_string, Json};
+
+    #[test]
+    fn strings_are_escaped() {
+        let mut rng = SmallRng::seed_from_u64(4711);
+        for _ in 0..1000 {
+            let mut s = String::new();
+            push_string(&mut rng, &mut s);
+            let parsed: String = serde_json::from_str(&s).unwrap();
+            assert!(!parsed.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn chunks_form_a_valid_array() {
+        let (tx, mut rx) = mpsc::channel(1);
+        Json::new(200).start(tx, &RequestContext::default());
+
+        let mut document = String::new();
+        for _ in 0..50 {
+            let chunk = rx.recv().await.unwrap();
+            assert!(chunk.len() >= 200);
+            document.push_str(std::str::from_utf8(&chunk).unwrap());
+
+            // Valid no matter where the stream is ended
+            let array: Vec<serde_json::Value> =
+                serde_json::from_str(&format!("{document}]")).unwrap();
+            assert!(array.iter().all(|record| record["data"].is_object()));
+        }
+    }
+
+    #[tokio::test]
+    async fn default_config_produces_valid_json() {
+        let mut config = GeneratorConfig::default();
+        config.size_limit = 10_000;
+        let g = Generator::from_config(Arc::new(config));
+        let (tx, mut rx) = mpsc::channel(100);
+        let context = RequestContext::default();
+        let (reason, _) = g.generate(Json::new(200), &context, &tx, |_| {}).await;
+        assert_eq!(reason, EndReason::SizeLimit);
+        drop(tx);
+
+        let mut body = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            body.extend(chunk);
+        }
+        let array: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert!(!array.is_empty());
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use tokio::sync::mpsc;
+use tracing::instrument;
+
+use crate::request_context::RequestContext;
+
+use super::{GeneratorStrategy, P_TAG_SIZE};
+
+/// Generates text mixed with links to more pages under `link_prefix`, so that crawlers following
+/// links keep coming back for more.
+///
+/// All output is derived from a seed based on the requested URI, so visiting the same page twice
+/// gives the same content, just like a real (but very large) static site. The seed of a sample is
+/// mixed in as well, to get a different maze.
+#[derive(Clone, Debug)]
+pub(crate) struct LinkMaze {
+    chunk_size: usize,
+    link_prefix: Arc<str>,
+}
+
+impl LinkMaze {
+    pub fn new(chunk_size: usize, link_prefix: &str) -> Self {
+        Self {
+            chunk_size,
+            link_prefix: Arc::from(link_prefix.trim_end_matches('/')),
+        }
+    }
+}
+
+/// 64-bit FNV-1a. Unlike the hasher in `std` it is guaranteed to be stable between versions, so
+/// pages look the same after an upgrade.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Pushes a random lowercase word to `s`.
+pub(super) fn push_word<R: Rng>(rng: &mut R, s: &mut String) {
+    let len = rng.random_range(2..=10);
+    s.extend((0..len).map(|_| char::from(rng.random_range(b'a'..=b'z'))));
+}
+
+impl GeneratorStrategy for LinkMaze {
+    fn name(&self) -> &'static str {
+        "link_maze"
+    }
+
+    #[instrument(name = "spawn_link_maze", skip_all)]
+    fn start(self, tx: mpsc::Sender<Bytes>, context: &RequestContext) {
+        let path = context.uri.path_and_query().map_or("/", |pq| pq.as_str());
+        let seed = fnv1a(path.as_bytes()) ^ context.seed.unwrap_or_default();
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let desired_size = self.chunk_size - P_TAG_SIZE;
+            let mut rng = SmallRng::seed_from_u64(seed);
+
+            loop {
+                let mut result = String::with_capacity(desired_size + 100);
+                while result.len() < desired_size {
+                    if rng.random_ratio(1, 12) {
+                        result.push_str("<a href=\"");
+                        result.push_str(&self.link_prefix);
+                        for _ in 0..rng.random_range(1..=3) {
+                            result.push('/');
+                            push_word(&mut rng, &mut result);
+                        }
+                        if rng.random_bool(0.5) {
+                            result.push_str(".html");
+                        }
+                        result.push_str("\">");
+                        push_word(&mut rng, &mut result);
+                        result.push_str("</a> ");
+                    } else {
+                        push_word(&mut rng, &mut result);
+                        result.push(' ');
+                    }
+                }
+
+                if tx
+                    .blocking_send(Bytes::from(format!("<p>\n{result}\n</p>\n")))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+}
@@ -1,12 +1,12 @@
-use std::{fs, path::Path, process::exit, sync::Arc};
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
 
 use bytes::Bytes;
-use markovish::Chain;
-use rand::{rngs::SmallRng, SeedableRng};
+use rand::{seq::IndexedRandom, Rng};
 use tokio::sync::mpsc::{self};
 use tracing::instrument;
+use unicode_segmentation::UnicodeSegmentation;
 
-use crate::error_code;
+use crate::{error_code, request_context::RequestContext};
 
 use super::{GeneratorStrategy, P_TAG_SIZE};
 
@@ -20,30 +20,159 @@ pub(crate) struct MarkovChain {
 }
 
 impl MarkovChain {
-    pub fn new(chunk_size: usize, input: &Path) -> Self {
-        let content = fs::read_to_string(input).unwrap_or_else(|e| {
-            println!("Could not create Markov chain generator due to error:\n\t{e}");
-            exit(error_code::CANNOT_READ_GENERATOR_DATA_FILE);
-        });
-
-        let chain: Chain =
-            Chain::from_text(&content).expect("could not create markov chain from file");
+    /// Creates a chain from the text in `input`.
+    ///
+    /// Returns an exit code if it cannot be read, or has too little text to build a chain from.
+    pub fn new(chunk_size: usize, input: &Path) -> Result<Self, i32> {
+        let chain = read_chain(input).map_err(|e| {
+            eprintln!("Could not create Markov chain generator, {e}");
+            error_code::CANNOT_READ_GENERATOR_DATA_FILE
+        })?;
 
-        Self {
+        Ok(Self {
             chain: Arc::new(chain),
             chunk_size,
+        })
+    }
+
+    /// Generates text of at most `n` tokens, such as a title or a short description. Any
+    /// whitespace, such as line breaks, is replaced by single spaces.
+    pub fn text<R: Rng>(&self, rng: &mut R, n: usize) -> String {
+        let text = self.chain.generate(rng, n).concat();
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// A second order Markov chain, where each token (a word, whitespace or a symbol) is picked based
+/// on the two tokens before it.
+///
+/// Tokens are kept in the order they first appear in the text, and hash maps are only used for
+/// lookups, so the same text and seed always give the same output.
+#[derive(Debug)]
+pub(crate) struct Chain {
+    /// Every distinct token of the text.
+    tokens: Vec<Box<str>>,
+    /// Every pair of tokens that is followed by another one, which generating can start from.
+    pairs: Vec<(u32, u32)>,
+    /// Where each pair is in `pairs`.
+    index: HashMap<(u32, u32), usize>,
+    /// Tokens following each pair in `pairs`, with their cumulative number of occurrences.
+    followers: Vec<Vec<(u32, u32)>>,
+}
+
+impl Chain {
+    /// Builds a chain from `text`, or returns `None` if it has less than three tokens.
+    pub fn from_text(text: &str) -> Option<Self> {
+        let mut ids = HashMap::new();
+        let mut tokens = Vec::new();
+        let text: Vec<u32> = text
+            .split_word_bounds()
+            .map(|token| {
+                *ids.entry(token).or_insert_with(|| {
+                    tokens.push(Box::from(token));
+                    u32::try_from(tokens.len() - 1).expect("fewer than 2^32 distinct tokens")
+                })
+            })
+            .collect();
+
+        let mut pairs = Vec::new();
+        let mut index = HashMap::new();
+        let mut followers: Vec<Vec<(u32, u32)>> = Vec::new();
+        // Where each follower of a pair is in `followers`, so that it can be counted quickly
+        let mut follower_index = HashMap::new();
+        for window in text.windows(3) {
+            let pair = (window[0], window[1]);
+            let i = *index.entry(pair).or_insert_with(|| {
+                pairs.push(pair);
+                followers.push(Vec::new());
+                pairs.len() - 1
+            });
+            let j = *follower_index.entry((i, window[2])).or_insert_with(|| {
+                followers[i].push((window[2], 0));
+                followers[i].len() - 1
+            });
+            followers[i][j].1 += 1;
+        }
+        if pairs.is_empty() {
+            return None;
+        }
+
+        // Cumulative counts let a follower be picked using a binary search
+        for counts in &mut followers {
+            let mut total = 0;
+            for (_, n) in counts.iter_mut() {
+                total += *n;
+                *n = total;
+            }
         }
+
+        Some(Self {
+            tokens,
+            pairs,
+            index,
+            followers,
+        })
+    }
+
+    /// Picks a random token that has followed `pair`, if any token has.
+    fn next<R: Rng>(&self, rng: &mut R, pair: (u32, u32)) -> Option<u32> {
+        let followers = &self.followers[*self.index.get(&pair)?];
+        let total = followers.last()?.1;
+        let n = rng.random_range(0..total);
+        let i = followers.partition_point(|&(_, cumulative)| cumulative <= n);
+        Some(followers[i].0)
+    }
+
+    /// Generates `n` tokens, starting over from a random pair whenever there is no token that
+    /// can follow.
+    pub fn generate<R: Rng>(&self, rng: &mut R, n: usize) -> Vec<&str> {
+        let mut generated = Vec::with_capacity(n + 1);
+        let mut pair = *self.pairs.choose(rng).expect("chains are never empty");
+        while generated.len() < n {
+            if let Some(next) = self.next(rng, pair) {
+                generated.push(next);
+                pair = (pair.1, next);
+            } else {
+                pair = *self.pairs.choose(rng).expect("chains are never empty");
+                generated.extend([pair.0, pair.1]);
+            }
+        }
+        generated.truncate(n);
+        generated
+            .into_iter()
+            .map(|token| &*self.tokens[token as usize])
+            .collect()
     }
 }
 
+/// Builds a chain from the text in `input`, or describes why it cannot be built.
+pub(crate) fn read_chain(input: &Path) -> Result<Chain, String> {
+    let content = fs::read_to_string(input).map_err(|e| {
+        format!(
+            "cannot read '{}' due to error:\n\t{e}",
+            input.to_string_lossy()
+        )
+    })?;
+    Chain::from_text(&content).ok_or_else(|| {
+        format!(
+            "'{}' has too little text to build a Markov chain from",
+            input.to_string_lossy()
+        )
+    })
+}
+
 impl GeneratorStrategy for MarkovChain {
+    fn name(&self) -> &'static str {
+        "markov_chain"
+    }
+
     #[instrument(name = "spawn_markov_chain", skip_all)]
-    fn start(self, tx: mpsc::Sender<Bytes>) {
+    fn start(self, tx: mpsc::Sender<Bytes>, context: &RequestContext) {
+        let mut smol_rng = context.rng();
         let span = tracing::Span::current();
         tokio::task::spawn_blocking(move || {
             let _entered = span.enter();
             let desired_size = self.chunk_size - P_TAG_SIZE;
-            let mut smol_rng = SmallRng::from_os_rng();
 
             loop {
                 let mut result = String::with_capacity(desired_size + 100);
@@ -59,15 +188,9 @@ impl GeneratorStrategy for MarkovChain {
                         break;
                     }
 
-                    let generated = &self.chain.generate_str(&mut smol_rng, likely_token_n);
-                    let Some(generated_strs) = generated else {
-                        tracing::error!("failed to generate string from chain");
-                        continue;
-                    };
-
                     // Cut off if we took too many
                     let mut current_size = 0;
-                    for s in generated_strs {
+                    for s in self.chain.generate(&mut smol_rng, likely_token_n) {
                         result.push_str(s);
                         current_size += s.len();
                         if current_size > size_left {
@@ -86,3 +209,31 @@ impl GeneratorStrategy for MarkovChain {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::Chain;
+
+    #[test]
+    fn needs_three_tokens() {
+        assert!(Chain::from_text("hi").is_none());
+        assert!(Chain::from_text("hi there").is_some());
+    }
+
+    #[test]
+    fn generates_same_tokens_for_same_seed() {
+        let text = "the cat sat on the mat and the dog sat on the cat";
+        let generate = |seed| {
+            let chain = Chain::from_text(text).unwrap();
+            chain
+                .generate(&mut SmallRng::seed_from_u64(seed), 50)
+                .concat()
+        };
+        let first = generate(1);
+        assert_eq!(first, generate(1));
+        assert_ne!(first, generate(2));
+        assert!(first.split(' ').all(|word| text.contains(word)));
+    }
+}
@@ -1,10 +1,6 @@
-use crate::config::GeneratorConfig;
+use crate::{config::GeneratorConfig, request_context::RequestContext};
 use bytes::Bytes;
-use rand::{
-    distr::{Alphanumeric, SampleString},
-    rngs::SmallRng,
-    SeedableRng,
-};
+use rand::distr::{Alphanumeric, SampleString};
 use tokio::sync::mpsc;
 use tracing::instrument;
 
@@ -23,13 +19,17 @@ impl Random {
 }
 
 impl GeneratorStrategy for Random {
+    fn name(&self) -> &'static str {
+        "random"
+    }
+
     #[instrument(name = "spawn_random", skip_all)]
-    fn start(self, tx: mpsc::Sender<Bytes>) {
+    fn start(self, tx: mpsc::Sender<Bytes>, context: &RequestContext) {
+        // No need to be secure, we are smacking bots
+        let mut smol_rng = context.rng();
         let span = tracing::Span::current();
         tokio::task::spawn_blocking(move || {
             let _entered = span.enter();
-            // No need to be secure, we are smacking bots
-            let mut smol_rng = SmallRng::from_os_rng();
             loop {
                 let s = Alphanumeric.sample_string(&mut smol_rng, self.chunk_size - P_TAG_SIZE);
                 let res = Bytes::from(format!("<p>\n{s}\n</p>\n"));
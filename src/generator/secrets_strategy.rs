@@ -0,0 +1,395 @@
+use std::net::IpAddr;
+
+use axum::http::Uri;
+use bytes::Bytes;
+use rand::{seq::IndexedRandom, Rng};
+use tokio::sync::mpsc;
+use tracing::instrument;
+
+use crate::{config::SecretsFormat, request_context::RequestContext};
+
+use super::{link_maze_strategy::push_word, GeneratorStrategy};
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const ALPHANUMERIC: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+/// What the unique part of AWS access key IDs is made of.
+const BASE32: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Keys of `.env` files whose values are not secret.
+const DOTENV_SETTINGS: &[&str] = &[
+    "APP_ENV",
+    "APP_URL",
+    "DB_HOST",
+    "DB_PORT",
+    "DB_DATABASE",
+    "DB_USERNAME",
+    "REDIS_HOST",
+    "MAIL_HOST",
+    "MAIL_USERNAME",
+    "AWS_DEFAULT_REGION",
+    "AWS_BUCKET",
+    "LOG_LEVEL",
+];
+
+/// Keys of `.env` files whose values are secret, and the prefix of their values.
+const DOTENV_SECRETS: &[(&str, &str)] = &[
+    ("DB_PASSWORD", ""),
+    ("REDIS_PASSWORD", ""),
+    ("MAIL_PASSWORD", ""),
+    ("APP_KEY", "base64:"),
+    ("JWT_SECRET", ""),
+    ("SECRET_KEY_BASE", ""),
+    ("AWS_ACCESS_KEY_ID", "AKIA"),
+    ("STRIPE_SECRET_KEY", "sk_live_"),
+    ("GITHUB_TOKEN", "ghp_"),
+    ("SLACK_BOT_TOKEN", "xoxb-"),
+    ("SENDGRID_API_KEY", "SG."),
+    ("OPENAI_API_KEY", "sk-proj-"),
+];
+
+const AWS_REGIONS: &[&str] = &[
+    "us-east-1",
+    "us-east-2",
+    "us-west-2",
+    "eu-west-1",
+    "eu-central-1",
+    "ap-southeast-1",
+];
+
+/// Generates endless, randomized files full of fake secrets, such as `.env` files or private
+/// keys.
+///
+/// Every secret contains a unique canary, which is logged together with the client it is for once
+/// the chunk with it is queued for sending. Seeing a canary somewhere else later, for example in
+/// a login attempt, tells where it came from. Queued chunks may never be sent if the stream ends,
+/// so the log can contain a few canaries that the client never got.
+///
+/// Every chunk ends with a complete line or key, so chunks never split a secret.
+#[derive(Clone, Debug)]
+pub(crate) struct Secrets {
+    chunk_size: usize,
+    format: SecretsFormat,
+}
+
+impl Secrets {
+    pub fn new(chunk_size: usize, format: SecretsFormat) -> Self {
+        Self { chunk_size, format }
+    }
+
+    /// Creates a chunk of at least `chunk_size` bytes, adding the canary of each secret in it
+    /// to `canaries`. The `first` chunk of a file starts with what real files start with.
+    fn chunk<R: Rng>(&self, rng: &mut R, first: bool, canaries: &mut Vec<String>) -> String {
+        let mut s = String::with_capacity(self.chunk_size + 1000);
+        if first {
+            match self.format {
+                SecretsFormat::Dotenv => s.push_str("APP_NAME=Laravel\nAPP_DEBUG=false\n"),
+                SecretsFormat::GitConfig => s.push_str(concat!(
+                    "[core]\n",
+                    "\trepositoryformatversion = 0\n",
+                    "\tfilemode = true\n",
+                    "\tbare = false\n",
+                    "\tlogallrefupdates = true\n",
+                )),
+                SecretsFormat::AwsCredentials => {
+                    push_aws_profile(rng, "default", canaries, &mut s);
+                }
+                SecretsFormat::PrivateKey => {}
+            }
+        }
+        while s.len() < self.chunk_size {
+            match self.format {
+                SecretsFormat::Dotenv => push_dotenv_section(rng, canaries, &mut s),
+                SecretsFormat::GitConfig => push_git_config_section(rng, canaries, &mut s),
+                SecretsFormat::AwsCredentials => {
+                    let mut profile = String::new();
+                    push_word(rng, &mut profile);
+                    push_aws_profile(rng, &profile, canaries, &mut s);
+                }
+                SecretsFormat::PrivateKey => push_private_key(rng, canaries, &mut s),
+            }
+        }
+        s
+    }
+}
+
+/// Pushes `n` random characters from `alphabet` to `s`.
+fn push_random<R: Rng>(rng: &mut R, alphabet: &[u8], n: usize, s: &mut String) {
+    s.extend((0..n).map(|_| char::from(*alphabet.choose(rng).expect("not empty"))));
+}
+
+/// Creates a new canary, and adds it to `canaries`.
+fn canary<R: Rng>(rng: &mut R, canaries: &mut Vec<String>) -> String {
+    let canary = format!("{:016x}", rng.random::<u64>());
+    canaries.push(canary.clone());
+    canary
+}
+
+/// Pushes an AWS access key ID, whose random unique part is used as its canary.
+fn push_aws_key_id<R: Rng>(rng: &mut R, canaries: &mut Vec<String>, s: &mut String) {
+    let mut canary = String::with_capacity(16);
+    push_random(rng, BASE32, 16, &mut canary);
+    s.push_str("AKIA");
+    s.push_str(&canary);
+    canaries.push(canary);
+}
+
+/// Pushes a secret starting with `prefix`, with a canary hidden in it. Secrets starting with
+/// `AKIA` are AWS access key IDs, which always have the same length.
+fn push_secret<R: Rng>(rng: &mut R, prefix: &str, canaries: &mut Vec<String>, s: &mut String) {
+    if prefix == "AKIA" {
+        push_aws_key_id(rng, canaries, s);
+        return;
+    }
+    let (before, after) = (rng.random_range(4..=12), rng.random_range(4..=24));
+    s.push_str(prefix);
+    push_random(rng, ALPHANUMERIC, before, s);
+    s.push_str(&canary(rng, canaries));
+    push_random(rng, ALPHANUMERIC, after, s);
+}
+
+/// Pushes a commented group of `.env` settings for a random service.
+fn push_dotenv_section<R: Rng>(rng: &mut R, canaries: &mut Vec<String>, s: &mut String) {
+    let mut service = String::new();
+    push_word(rng, &mut service);
+    s.push_str(&format!("\n# {service}\n"));
+    let service = service.to_uppercase();
+    for _ in 0..rng.random_range(2..=8) {
+        if rng.random_bool(0.6) {
+            let (key, prefix) = DOTENV_SECRETS.choose(rng).expect("not empty");
+            s.push_str(&format!("{service}_{key}="));
+            push_secret(rng, prefix, canaries, s);
+        } else {
+            let key = DOTENV_SETTINGS.choose(rng).expect("not empty");
+            s.push_str(&format!("{service}_{key}="));
+            if key.ends_with("_HOST") {
+                s.push_str(&format!(
+                    "10.{}.{}.{}",
+                    rng.random_range(0..=255),
+                    rng.random_range(0..=255),
+                    rng.random_range(1..=254)
+                ));
+            } else if key.ends_with("_PORT") {
+                s.push_str(&rng.random_range(1024..=65535).to_string());
+            } else if key.ends_with("_REGION") {
+                s.push_str(AWS_REGIONS.choose(rng).expect("not empty"));
+            } else if key.ends_with("_URL") {
+                s.push_str("https://");
+                push_word(rng, s);
+                s.push_str(".internal");
+            } else {
+                push_word(rng, s);
+            }
+        }
+        s.push('\n');
+    }
+}
+
+/// Pushes a `.git/config` section, where remotes have tokens in their URLs.
+fn push_git_config_section<R: Rng>(rng: &mut R, canaries: &mut Vec<String>, s: &mut String) {
+    let mut name = String::new();
+    push_word(rng, &mut name);
+    match rng.random_range(0..3) {
+        0 => {
+            let host = ["github.com", "gitlab.com", "bitbucket.org"]
+                .choose(rng)
+                .expect("not empty");
+            s.push_str(&format!("[remote \"{name}\"]\n\turl = https://"));
+            push_word(rng, s);
+            s.push(':');
+            push_secret(rng, "ghp_", canaries, s);
+            s.push_str(&format!("@{host}/"));
+            push_word(rng, s);
+            s.push('/');
+            push_word(rng, s);
+            s.push_str(&format!(
+                ".git\n\tfetch = +refs/heads/*:refs/remotes/{name}/*\n"
+            ));
+        }
+        1 => {
+            s.push_str(&format!(
+                "[branch \"{name}\"]\n\tremote = origin\n\tmerge = refs/heads/{name}\n"
+            ));
+        }
+        _ => {
+            s.push_str("[http \"https://");
+            push_word(rng, s);
+            s.push_str(".internal/\"]\n\textraheader = AUTHORIZATION: bearer ");
+            push_secret(rng, "", canaries, s);
+            s.push('\n');
+        }
+    }
+}
+
+/// Pushes a profile of an AWS credentials file.
+fn push_aws_profile<R: Rng>(
+    rng: &mut R,
+    profile: &str,
+    canaries: &mut Vec<String>,
+    s: &mut String,
+) {
+    s.push_str(&format!("[{profile}]\naws_access_key_id = "));
+    push_aws_key_id(rng, canaries, s);
+    s.push_str("\naws_secret_access_key = ");
+    push_random(rng, BASE64, 40, s);
+    s.push('\n');
+    if rng.random_bool(0.3) {
+        s.push_str("aws_session_token = ");
+        let length = rng.random_range(200..=400);
+        push_random(rng, BASE64, length, s);
+        s.push('\n');
+    }
+    s.push_str(&format!(
+        "region = {}\n\n",
+        AWS_REGIONS.choose(rng).expect("not empty")
+    ));
+}
+
+/// Pushes a PEM block that looks like a private key, with a canary right after its header.
+fn push_private_key<R: Rng>(rng: &mut R, canaries: &mut Vec<String>, s: &mut String) {
+    // What the encoded keys start with, so they look right at a glance
+    let (label, start, line_length, lines) = if rng.random_bool(0.5) {
+        (
+            "OPENSSH PRIVATE KEY",
+            "b3BlbnNzaC1rZXktdjEAAAAA",
+            70,
+            20..=50,
+        )
+    } else {
+        ("RSA PRIVATE KEY", "MIIEpAIBAAKCAQEA", 64, 25..=26)
+    };
+    let mut body = format!("{start}{}", canary(rng, canaries));
+    let length = line_length * rng.random_range(lines) - rng.random_range(0..line_length);
+    push_random(rng, BASE64, length - body.len(), &mut body);
+
+    s.push_str(&format!("-----BEGIN {label}-----\n"));
+    // Only ASCII, so any index is a character boundary
+    for line in body.as_bytes().chunks(line_length) {
+        s.push_str(std::str::from_utf8(line).expect("only ASCII"));
+        s.push('\n');
+    }
+    s.push_str(&format!("-----END {label}-----\n"));
+}
+
+/// Logs the canaries of secrets that have been queued for sending.
+fn log_canaries(canaries: &[String], client_ip: Option<IpAddr>, uri: &Uri) {
+    tracing::info!(
+        canaries = canaries.join(","),
+        client_ip = client_ip.map(tracing::field::display),
+        uri = %uri,
+        "Queued {} fake secrets",
+        canaries.len(),
+    );
+}
+
+impl GeneratorStrategy for Secrets {
+    fn name(&self) -> &'static str {
+        self.format.as_str()
+    }
+
+    // An HTML prefix would give it away
+    fn prepend_prefix(&self) -> bool {
+        false
+    }
+
+    #[instrument(name = "spawn_secrets", skip_all)]
+    fn start(self, tx: mpsc::Sender<Bytes>, context: &RequestContext) {
+        let mut rng = context.rng();
+        let client_ip = context.client_ip;
+        let uri = context.uri.clone();
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let mut canaries = Vec::new();
+            let mut first = true;
+            loop {
+                let chunk = self.chunk(&mut rng, first, &mut canaries);
+                first = false;
+
+                if tx.blocking_send(Bytes::from(chunk)).is_err() {
+                    break;
+                }
+                log_canaries(&canaries, client_ip, &uri);
+                canaries.clear();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use crate::config::SecretsFormat;
+
+    use super::{Secrets, BASE32};
+
+    /// The first `n` chunks of `format`, and the canaries in them.
+    fn chunks(format: SecretsFormat, n: usize) -> (String, Vec<String>) {
+        let secrets = Secrets::new(1000, format);
+        let mut rng = SmallRng::seed_from_u64(4711);
+        let mut canaries = Vec::new();
+        let file = (0..n)
+            .map(|i| {
+                let chunk = secrets.chunk(&mut rng, i == 0, &mut canaries);
+                assert!(chunk.len() >= 1000);
+                assert!(chunk.ends_with('\n'));
+                chunk
+            })
+            .collect();
+        (file, canaries)
+    }
+
+    #[test]
+    fn every_secret_has_a_unique_canary() {
+        for format in [
+            SecretsFormat::Dotenv,
+            SecretsFormat::GitConfig,
+            SecretsFormat::AwsCredentials,
+            SecretsFormat::PrivateKey,
+        ] {
+            let (file, canaries) = chunks(format, 5);
+            assert!(!canaries.is_empty());
+            assert_eq!(
+                canaries.iter().collect::<HashSet<_>>().len(),
+                canaries.len()
+            );
+            let lowercase = file.to_lowercase();
+            for canary in &canaries {
+                let canary = canary.to_lowercase();
+                assert_eq!(lowercase.matches(canary.as_str()).count(), 1, "{canary}");
+            }
+        }
+    }
+
+    #[test]
+    fn files_look_right() {
+        let (dotenv, _) = chunks(SecretsFormat::Dotenv, 5);
+        assert!(dotenv.starts_with("APP_NAME="));
+        assert!(dotenv
+            .lines()
+            .all(|line| line.is_empty() || line.starts_with("# ") || line.contains('=')));
+
+        let (git_config, _) = chunks(SecretsFormat::GitConfig, 5);
+        assert!(git_config.starts_with("[core]\n"));
+        assert!(git_config
+            .lines()
+            .all(|line| line.starts_with('[') || line.starts_with('\t')));
+
+        let (aws, canaries) = chunks(SecretsFormat::AwsCredentials, 5);
+        assert!(aws.starts_with("[default]\naws_access_key_id = AKIA"));
+        let key_id = format!("AKIA{}", canaries[0]);
+        assert_eq!(key_id.len(), 20);
+        assert!(canaries[0].bytes().all(|b| BASE32.contains(&b)));
+        assert!(aws.contains(&key_id));
+
+        let (keys, _) = chunks(SecretsFormat::PrivateKey, 5);
+        assert!(keys.starts_with("-----BEGIN "));
+        assert_eq!(
+            keys.matches("-----BEGIN ").count(),
+            keys.matches("-----END ").count()
+        );
+        assert!(keys.lines().all(|line| line.len() <= 70));
+    }
+}
@@ -1,11 +1,11 @@
-use std::{fs, path::Path, process::exit};
+use std::{fs, path::Path};
 
 use tokio::sync::mpsc;
 
 use bytes::Bytes;
 use tracing::{instrument, Instrument};
 
-use crate::error_code;
+use crate::{error_code, request_context::RequestContext};
 
 use super::GeneratorStrategy;
 
@@ -16,20 +16,35 @@ pub(crate) struct Static {
 }
 
 impl Static {
-    pub fn new(input: &Path) -> Self {
-        let data = fs::read_to_string(input).unwrap_or_else(|_| {
-            println!("Data for static generator must be a path to a readable file.");
-            exit(error_code::CANNOT_READ_GENERATOR_DATA_FILE);
-        });
-        Self {
+    /// Reads the data to send from `input`.
+    ///
+    /// Returns an exit code if it cannot be read.
+    pub fn new(input: &Path) -> Result<Self, i32> {
+        let data = fs::read_to_string(input).map_err(|e| {
+            eprintln!(
+                "failed to read '{}' due to error:\n\t{e}",
+                input.to_string_lossy()
+            );
+            error_code::CANNOT_READ_GENERATOR_DATA_FILE
+        })?;
+        Ok(Self {
             data: Bytes::from(data),
-        }
+        })
+    }
+
+    /// The data that is sent over and over again.
+    pub fn data(&self) -> Bytes {
+        self.data.clone()
     }
 }
 
 impl GeneratorStrategy for Static {
+    fn name(&self) -> &'static str {
+        "static"
+    }
+
     #[instrument(name = "spawn_static", skip_all)]
-    fn start(self, tx: mpsc::Sender<Bytes>) {
+    fn start(self, tx: mpsc::Sender<Bytes>, _context: &RequestContext) {
         // Cloning a `Bytes` is very cheap, so this does not need to be blocking
         tokio::task::spawn(
             async move {
@@ -0,0 +1,492 @@
+//! Types shared by the different kinds of listeners that `pandoras_pot` can serve on.
+
+use std::{fmt, future::Future, io, net::SocketAddr, time::Duration};
+
+use axum::{
+    extract::connect_info::Connected,
+    serve::{IncomingStream, Listener},
+    Router,
+};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::{net::TcpListener, sync::mpsc, task::JoinSet};
+
+use crate::{
+    config::{ListenAddress, ListenerConfig},
+    proxy_protocol::ProxyProtocol,
+    shutdown,
+};
+
+/// Max time a client gets to complete a handshake. Bots are not getting any free connections.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many connections that have finished their handshake can wait to be served.
+const ACCEPT_BACKLOG: usize = 64;
+
+/// Address of the peer of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PeerAddr {
+    Tcp(SocketAddr),
+    /// Connected over a unix socket, which will generally be a reverse proxy. Peers of unix
+    /// sockets do not have any address worth knowing.
+    Unix,
+}
+
+impl PeerAddr {
+    /// The address of a peer connected over TCP.
+    pub fn socket_addr(self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(addr) => Some(addr),
+            Self::Unix => None,
+        }
+    }
+}
+
+/// What is known about the other end of a connection, made available to handlers as
+/// [`ConnectInfo`](axum::extract::ConnectInfo).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ConnectionInfo {
+    pub peer: PeerAddr,
+    /// Address of the client according to a PROXY protocol header sent by the peer.
+    pub proxied: Option<SocketAddr>,
+}
+
+impl ConnectionInfo {
+    /// The address the connection originates from, as far as we can trust it.
+    pub fn origin_addr(self) -> Option<SocketAddr> {
+        self.proxied.or(self.peer.socket_addr())
+    }
+}
+
+impl fmt::Display for ConnectionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.peer {
+            PeerAddr::Tcp(addr) => write!(f, "{addr}")?,
+            PeerAddr::Unix => write!(f, "unix socket peer")?,
+        }
+        if let Some(proxied) = self.proxied {
+            write!(f, " (proxying {proxied})")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<SocketAddr> for ConnectionInfo {
+    fn from(addr: SocketAddr) -> Self {
+        Self {
+            peer: PeerAddr::Tcp(addr),
+            proxied: None,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl From<tokio::net::unix::SocketAddr> for ConnectionInfo {
+    fn from(_addr: tokio::net::unix::SocketAddr) -> Self {
+        Self {
+            peer: PeerAddr::Unix,
+            proxied: None,
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for ConnectionInfo {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        (*stream.remote_addr()).into()
+    }
+}
+
+#[cfg(unix)]
+impl Connected<IncomingStream<'_, UnixListener>> for ConnectionInfo {
+    fn connect_info(stream: IncomingStream<'_, UnixListener>) -> Self {
+        stream.remote_addr().clone().into()
+    }
+}
+
+impl<Io> Connected<IncomingStream<'_, HandshakeListener<Io>>> for ConnectionInfo
+where
+    HandshakeListener<Io>: Listener<Addr = Self>,
+{
+    fn connect_info(stream: IncomingStream<'_, HandshakeListener<Io>>) -> Self {
+        *stream.remote_addr()
+    }
+}
+
+/// Something done on new connections before they are served, such as a TLS handshake.
+pub(crate) trait Handshake<Io>: Clone + Send + Sync + 'static {
+    /// The connection after the handshake.
+    type Io;
+
+    /// Used in logs, such as "TLS handshake".
+    const NAME: &'static str;
+
+    /// Performs the handshake, possibly learning more about the connection.
+    fn handshake(
+        &self,
+        io: Io,
+        info: &mut ConnectionInfo,
+    ) -> impl Future<Output = io::Result<Self::Io>> + Send;
+}
+
+/// A listener that only hands out connections that have completed a [`Handshake`].
+///
+/// Handshakes are done in separate tasks, so slow clients cannot hold up anyone else.
+pub(crate) struct HandshakeListener<Io> {
+    incoming: mpsc::Receiver<(Io, ConnectionInfo)>,
+    local_addr: ConnectionInfo,
+}
+
+impl<Io: Send + 'static> HandshakeListener<Io> {
+    pub fn new<L, H>(listener: L, handshake: H) -> io::Result<Self>
+    where
+        L: Listener,
+        L::Addr: Into<ConnectionInfo>,
+        H: Handshake<L::Io, Io = Io>,
+    {
+        let local_addr = listener.local_addr()?.into();
+        let (tx, incoming) = mpsc::channel(ACCEPT_BACKLOG);
+        tokio::spawn(accept_loop(listener, handshake, tx));
+        Ok(Self {
+            incoming,
+            local_addr,
+        })
+    }
+}
+
+/// Accepts connections and performs handshakes until `tx` is closed.
+async fn accept_loop<L, H>(mut listener: L, handshake: H, tx: mpsc::Sender<(H::Io, ConnectionInfo)>)
+where
+    L: Listener,
+    L::Addr: Into<ConnectionInfo>,
+    H: Handshake<L::Io>,
+    H::Io: Send + 'static,
+{
+    loop {
+        let (stream, addr) = tokio::select! {
+            () = tx.closed() => return,
+            accepted = listener.accept() => accepted,
+        };
+
+        let handshake = handshake.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let mut info = addr.into();
+            let result =
+                tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake.handshake(stream, &mut info));
+            match result.await {
+                Ok(Ok(stream)) => {
+                    let _ = tx.send((stream, info)).await;
+                }
+                Ok(Err(e)) => tracing::debug!("{} with {info} failed: {e}", H::NAME),
+                Err(_) => tracing::debug!("{} with {info} timed out", H::NAME),
+            }
+        });
+    }
+}
+
+impl<Io> Listener for HandshakeListener<Io>
+where
+    Io: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    type Io = Io;
+    type Addr = ConnectionInfo;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        // The accept loop only stops once we are dropped
+        self.incoming
+            .recv()
+            .await
+            .expect("accept loop stopped unexpectedly")
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// A listener bound to one of the configured addresses.
+#[derive(Debug)]
+pub(crate) enum BoundListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// Binds a listener to the address in `config`. Unix sockets left behind by a previous run are
+/// replaced.
+pub(crate) async fn bind(config: &ListenerConfig) -> io::Result<BoundListener> {
+    match &config.address {
+        ListenAddress::Tcp(addr) => TcpListener::bind(addr).await.map(BoundListener::Tcp),
+        #[cfg(unix)]
+        ListenAddress::Unix(path) => unix::bind(path, config).map(BoundListener::Unix),
+        #[cfg(not(unix))]
+        ListenAddress::Unix(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unix sockets are not supported on this platform",
+        )),
+    }
+}
+
+/// Serves `app` on `listener` in a new task, reading PROXY protocol headers and doing TLS
+/// handshakes first if configured. The task ends once we are shutting down and all of its
+/// connections are closed.
+pub(crate) fn serve(
+    servers: &mut JoinSet<io::Result<()>>,
+    listener: BoundListener,
+    config: &ListenerConfig,
+    app: Router,
+    #[cfg(feature = "tls")] tls: Option<&tokio_rustls::TlsAcceptor>,
+) -> io::Result<()> {
+    match listener {
+        BoundListener::Tcp(listener) => {
+            // Unix sockets are meant for a reverse proxy on the same machine, so only TCP gets TLS
+            #[cfg(feature = "tls")]
+            if let Some(acceptor) = tls {
+                if config.proxy_protocol {
+                    let listener = HandshakeListener::new(listener, ProxyProtocol)?;
+                    spawn(
+                        servers,
+                        HandshakeListener::new(listener, acceptor.clone())?,
+                        app,
+                    );
+                } else {
+                    spawn(
+                        servers,
+                        HandshakeListener::new(listener, acceptor.clone())?,
+                        app,
+                    );
+                }
+                return Ok(());
+            }
+
+            if config.proxy_protocol {
+                spawn(
+                    servers,
+                    HandshakeListener::new(listener, ProxyProtocol)?,
+                    app,
+                );
+            } else {
+                spawn(servers, listener, app);
+            }
+        }
+        #[cfg(unix)]
+        BoundListener::Unix(listener) => {
+            if config.proxy_protocol {
+                spawn(
+                    servers,
+                    HandshakeListener::new(listener, ProxyProtocol)?,
+                    app,
+                );
+            } else {
+                spawn(servers, listener, app);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn spawn<L>(servers: &mut JoinSet<io::Result<()>>, listener: L, app: Router)
+where
+    L: Listener,
+    L::Addr: fmt::Debug,
+    for<'a> ConnectionInfo: Connected<IncomingStream<'a, L>>,
+{
+    let app = app.into_make_service_with_connect_info::<ConnectionInfo>();
+    servers.spawn(async move {
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown::stopping().cancelled_owned())
+            .await
+    });
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs::{self, Permissions},
+        io,
+        os::unix::{
+            fs::{FileTypeExt, PermissionsExt},
+            net::UnixStream,
+        },
+        path::Path,
+    };
+
+    use nix::unistd::{Group, User};
+    use tokio::net::UnixListener;
+
+    use crate::config::ListenerConfig;
+
+    /// Binds a unix socket at `path`, and sets its mode and owner.
+    pub(super) fn bind(path: &Path, config: &ListenerConfig) -> io::Result<UnixListener> {
+        remove_stale_socket(path)?;
+        let listener = UnixListener::bind(path)?;
+
+        if let Some(mode) = config.mode {
+            fs::set_permissions(path, Permissions::from_mode(mode))?;
+        }
+
+        if config.owner.is_some() || config.group.is_some() {
+            let uid = config.owner.as_deref().map(user_id).transpose()?;
+            let gid = config.group.as_deref().map(group_id).transpose()?;
+            std::os::unix::fs::chown(path, uid, gid)?;
+        }
+
+        Ok(listener)
+    }
+
+    /// Removes a socket left behind by a previous run, unless something is still listening on
+    /// it. Other kinds of files are never removed.
+    fn remove_stale_socket(path: &Path) -> io::Result<()> {
+        match fs::symlink_metadata(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+            Ok(metadata) if !metadata.file_type().is_socket() => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "file exists and is not a socket",
+            )),
+            Ok(_) if UnixStream::connect(path).is_ok() => Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "socket is in use by another process",
+            )),
+            Ok(_) => fs::remove_file(path),
+        }
+    }
+
+    /// Looks up a user by name, unless it is already a numeric id.
+    fn user_id(user: &str) -> io::Result<u32> {
+        if let Ok(uid) = user.parse() {
+            return Ok(uid);
+        }
+        User::from_name(user)?
+            .map(|u| u.uid.as_raw())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no user '{user}'")))
+    }
+
+    /// Looks up a group by name, unless it is already a numeric id.
+    fn group_id(group: &str) -> io::Result<u32> {
+        if let Ok(gid) = group.parse() {
+            return Ok(gid);
+        }
+        Group::from_name(group)?
+            .map(|g| g.gid.as_raw())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no group '{group}'")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::{fs, io, os::unix::fs::PermissionsExt};
+
+    use axum::{extract::ConnectInfo, routing::get, Router};
+    #[cfg(unix)]
+    use tokio::net::UnixStream;
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        task::JoinSet,
+    };
+
+    use crate::config::{ListenAddress, ListenerConfig};
+
+    use super::{bind, serve, BoundListener, ConnectionInfo};
+
+    /// An app responding with what it knows about the connection.
+    fn connection_info_app() -> Router {
+        Router::new().route(
+            "/",
+            get(|ConnectInfo(info): ConnectInfo<ConnectionInfo>| async move { format!("{info}") }),
+        )
+    }
+
+    /// Sends `preamble` followed by a request, and returns everything received.
+    async fn request<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, preamble: &str) -> String {
+        stream.write_all(preamble.as_bytes()).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        // Connections may be reset if they are dropped
+        let _ = stream.read_to_string(&mut response).await;
+        response
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_on_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pandoras_pot.sock");
+        // Left behind by a previous run
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let mut config = ListenerConfig::new(ListenAddress::Unix(path.clone()));
+        config.mode = Some(0o600);
+        let BoundListener::Unix(listener) = bind(&config).await.unwrap() else {
+            panic!("not bound to a unix socket");
+        };
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o600);
+
+        // Now that something is listening, the socket must be left alone
+        let err = bind(&config).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        let app = connection_info_app();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<ConnectionInfo>(),
+            )
+            .await
+            .unwrap();
+        });
+
+        let response = request(UnixStream::connect(&path).await.unwrap(), "").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("unix socket peer"), "{response}");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn does_not_replace_other_files() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let config = ListenerConfig::new(ListenAddress::Unix(file.path().to_path_buf()));
+
+        let err = bind(&config).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(file.path().is_file());
+    }
+
+    #[tokio::test]
+    async fn reads_proxy_protocol_header() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut config = ListenerConfig::new(ListenAddress::Tcp(addr));
+        config.proxy_protocol = true;
+
+        let mut servers = JoinSet::new();
+        serve(
+            &mut servers,
+            BoundListener::Tcp(listener),
+            &config,
+            connection_info_app(),
+            #[cfg(feature = "tls")]
+            None,
+        )
+        .unwrap();
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let local_addr = stream.local_addr().unwrap();
+        let response = request(stream, "PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(
+            response.ends_with(&format!("{local_addr} (proxying 192.0.2.1:56324)")),
+            "{response}"
+        );
+
+        // Clients cannot skip the header
+        let response = request(TcpStream::connect(addr).await.unwrap(), "").await;
+        assert!(response.is_empty(), "{response}");
+    }
+}
@@ -1,23 +1,47 @@
 #![forbid(unsafe_code)]
 mod args;
+mod bench;
+mod check;
+mod client_ip;
+mod client_limits;
 mod config;
 mod error_code;
 mod generator;
 mod handler;
+mod listener;
+mod metrics;
+mod overflow;
+mod proxy_protocol;
+mod reload;
+mod request_context;
+mod sample;
+mod shutdown;
 mod stream_body;
-
-use args::parse_args;
+#[cfg(feature = "tls")]
+mod tls;
+use args::{parse_args, Command};
 use axum::{
     error_handling::HandleErrorLayer,
-    http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, StatusCode},
-    response::IntoResponse,
+    extract::ConnectInfo,
+    http::{
+        header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, VARY},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
     routing::{get, on, MethodFilter},
     BoxError, Router,
 };
-use std::{fs, process::exit, sync::Arc, time::Duration};
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::exit,
+    sync::Arc,
+    time::Duration,
+};
 use stream_body::StreamBody;
-use tokio::net::TcpListener;
-use tower::{buffer::BufferLayer, limit::RateLimitLayer, ServiceBuilder};
+use tokio::task::JoinSet;
+use tower::{buffer::BufferLayer, limit::RateLimitLayer, util::MapRequestLayer, ServiceBuilder};
 use tower_http::trace::MakeSpan;
 use tracing::info_span;
 use tracing_subscriber::prelude::*;
@@ -26,9 +50,22 @@ use config::Config;
 use generator::{random_strategy::Random, Generator, GeneratorStrategyContainer};
 
 use crate::{
-    config::GeneratorType,
-    generator::{markov_strategy::MarkovChain, static_strategy::Static, P_TAG_SIZE},
+    check::Problem,
+    client_ip::TrustedProxies,
+    client_limits::ClientLimits,
+    config::{
+        ConfigError, GeneratorConfig, GeneratorType, HttpConfig, ListenAddress, ListenerConfig,
+    },
+    generator::{
+        compression_bomb_strategy::CompressionBomb, feed_strategy::Feed, html_strategy::Html,
+        json_strategy::Json, link_maze_strategy::LinkMaze, markov_strategy::MarkovChain,
+        secrets_strategy::Secrets, static_strategy::Static,
+    },
     handler::RequestHandler,
+    listener::{BoundListener, ConnectionInfo},
+    overflow::Overflow,
+    reload::{Hangups, ReloadableRouter},
+    request_context::RequestContext,
 };
 
 const ANY_METHOD: MethodFilter = MethodFilter::DELETE
@@ -53,97 +90,323 @@ impl<B> MakeSpan<B> for PandoraRequestSpan {
             axum::http::Version::HTTP_3 => "HTTP/3".to_string(),
             _ => "UNKNOWN".to_string(),
         };
+        // The peer will generally be the reverse proxy, unless it told us otherwise using the
+        // PROXY protocol
+        let origin_ip = request
+            .extensions()
+            .get::<ConnectInfo<ConnectionInfo>>()
+            .and_then(|ConnectInfo(info)| info.origin_addr())
+            .map(|addr| addr.ip().to_string());
         info_span!(
             "request",
             version = version_string,
             method = request.method().to_string(),
             uri = request.uri().to_string(),
             proxied_ip = tracing::field::Empty, // Set later by our RequestHandler
-            origin_ip = tracing::field::Empty,  // TODO: Same as above, will generally be the
-                                                // reverse proxy
+            origin_ip,
         )
     }
 }
 
-#[allow(clippy::unused_async)]
-async fn text_stream(
+/// Everything needed to serve a single route.
+#[derive(Clone, Debug)]
+struct RouteState {
     content_type: HeaderValue,
     generator: Generator,
     generator_strategy: GeneratorStrategyContainer,
-) -> impl IntoResponse {
+    /// Sent instead of generated data to clients accepting a suitable encoding.
+    compression_bomb: Option<CompressionBomb>,
+    /// Sent instead of generated data if there are no generators available.
+    overflow: Overflow,
+    client_limits: ClientLimits,
+}
+
+impl RouteState {
+    /// Creates the generator and strategy used for a route. If the route replaces one that was
+    /// using `previous`, its permits are kept when they are unchanged.
+    ///
+    /// Returns an exit code in case of configuration errors.
+    fn new(
+        generator_config: GeneratorConfig,
+        content_type: &str,
+        client_limits: ClientLimits,
+        previous: Option<&Generator>,
+    ) -> Result<Self, i32> {
+        let generator_strategy = match &generator_config.generator_type {
+            GeneratorType::Random => {
+                GeneratorStrategyContainer::Random(Random::new(generator_config.chunk_size))
+            }
+            GeneratorType::MarkovChain(input) => GeneratorStrategyContainer::MarkovChain(
+                MarkovChain::new(generator_config.chunk_size, input)?,
+            ),
+            GeneratorType::Static(input) => GeneratorStrategyContainer::Static(Static::new(input)?),
+            GeneratorType::LinkMaze(link_prefix) => GeneratorStrategyContainer::LinkMaze(
+                LinkMaze::new(generator_config.chunk_size, link_prefix),
+            ),
+            GeneratorType::Json => {
+                GeneratorStrategyContainer::Json(Json::new(generator_config.chunk_size))
+            }
+            GeneratorType::Feed(feed) => {
+                GeneratorStrategyContainer::Feed(Feed::new(generator_config.chunk_size, feed)?)
+            }
+            GeneratorType::Html(html) => {
+                GeneratorStrategyContainer::Html(Html::new(generator_config.chunk_size, html)?)
+            }
+            GeneratorType::Secrets(format) => GeneratorStrategyContainer::Secrets(Secrets::new(
+                generator_config.chunk_size,
+                *format,
+            )),
+        };
+
+        let content_type = content_type.parse().map_err(|e| {
+            eprintln!(
+                "cannot parse content_type '{content_type}' to valid header due to error: {e}"
+            );
+            error_code::BAD_CONTENT_TYPE
+        })?;
+
+        let compression_bomb = if generator_config.compression_bomb.is_empty() {
+            None
+        } else {
+            let compression_bomb = CompressionBomb::new(
+                &generator_config.compression_bomb,
+                &generator_config.prefix,
+                generator_config.chunk_size,
+            )
+            .map_err(|e| {
+                eprintln!("Could not create compression bombs due to error:\n\t{e}");
+                error_code::CANNOT_CREATE_COMPRESSION_BOMB
+            })?;
+            Some(compression_bomb)
+        };
+
+        let overflow = Overflow::new(&generator_config.overflow_policy)?;
+
+        let generator_config = Arc::new(generator_config);
+        let generator = match previous {
+            Some(previous) => previous.reconfigured(generator_config),
+            None => Generator::from_config(generator_config),
+        };
+
+        Ok(Self {
+            content_type,
+            generator,
+            generator_strategy,
+            compression_bomb,
+            overflow,
+            client_limits,
+        })
+    }
+}
+
+async fn text_stream(route: RouteState, context: RequestContext) -> Response {
+    let permit = match route.client_limits.admit(context.client_ip).await {
+        Ok(permit) => permit,
+        Err(response) => return response,
+    };
+
     // Set some headers to trick le bots
     let mut headers = HeaderMap::new();
-    headers.insert(CONTENT_TYPE, content_type);
+    headers.insert(CONTENT_TYPE, route.content_type);
 
-    match generator_strategy {
-        GeneratorStrategyContainer::Random(g) => {
-            StreamBody::from_stream(generator.into_stream(g)).headers(headers)
-        }
-        GeneratorStrategyContainer::MarkovChain(g) => {
-            StreamBody::from_stream(generator.into_stream(g)).headers(headers)
-        }
-        GeneratorStrategyContainer::Static(g) => {
-            StreamBody::from_stream(generator.into_stream(g)).headers(headers)
+    let generator = route.generator;
+    let Some(generator_permit) = generator.acquire_permit().await else {
+        tracing::info!(
+            "No generator available, overflowing using policy: {}",
+            generator.config().overflow_policy
+        );
+        return route.overflow.response(headers, generator.config(), permit);
+    };
+
+    let bomb = route
+        .compression_bomb
+        .and_then(|bombs| bombs.negotiate(context.headers.get(ACCEPT_ENCODING)));
+    let body = if let Some(bomb) = bomb {
+        headers.insert(
+            CONTENT_ENCODING,
+            HeaderValue::from_static(bomb.encoding().as_str()),
+        );
+        headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+        StreamBody::from_stream(generator.into_stream(bomb, context, generator_permit))
+    } else {
+        StreamBody::from_stream(generator.into_stream(
+            route.generator_strategy,
+            context,
+            generator_permit,
+        ))
+    };
+
+    body.headers(headers).permit(permit).into_response()
+}
+
+/// The handlers for all configured routes, shared by every listener so that limits such as
+/// `generator.max_concurrent` apply to all of them together.
+#[derive(Clone, Debug)]
+struct Routes {
+    /// Used for any route without settings of its own.
+    default: RouteState,
+    /// Routes from `[[routes]]`, with their paths.
+    configured: Vec<(String, RouteState)>,
+    client_limits: ClientLimits,
+}
+
+impl Routes {
+    /// Creates the handlers of all routes in a config, which must have been checked using
+    /// [`check::check`].
+    ///
+    /// Returns an exit code if a file used by a route cannot be read.
+    fn new(config: &Config) -> Result<Self, i32> {
+        Self::build(config, None)
+    }
+
+    /// Creates the handlers of all routes in a reloaded config, keeping the limits of these
+    /// routes where they are unchanged. Streams that are already running then still count
+    /// towards them, instead of the limits starting over.
+    ///
+    /// Returns an exit code if a file used by a route cannot be read.
+    fn reload(&self, config: &Config) -> Result<Self, i32> {
+        let routes = Self::build(config, Some(self))?;
+        self.default
+            .generator
+            .retire(Some(&routes.default.generator));
+        for (path, route_state) in &self.configured {
+            let replacement = routes.configured(path).map(|r| &r.generator);
+            route_state.generator.retire(replacement);
         }
+        Ok(routes)
     }
-}
 
-/// Creates a new app from a config.
-///
-/// Returns an exit code in case of configuration errors.
-fn create_app(config: &Config) -> Result<Router, i32> {
-    // This will mess upp for example markov
-    if config.generator.chunk_size < P_TAG_SIZE {
-        eprintln!(
-            "generator.chunk_size too small (min size is {P_TAG_SIZE}, but it should be bigger!)"
-        );
-        return Err(error_code::GENERATOR_CHUNK_SIZE_TOO_SMALL);
+    fn build(config: &Config, previous: Option<&Self>) -> Result<Self, i32> {
+        let client_limits = match previous {
+            Some(previous) if *previous.client_limits.config() == config.http.client_limits => {
+                previous.client_limits.clone()
+            }
+            _ => ClientLimits::new(config.http.client_limits.clone())
+                .expect("http.client_limits.max_clients is checked"),
+        };
+
+        // Create gen depending on config
+        tracing::info!("Using generator: {}", config.generator.generator_type);
+        let default = RouteState::new(
+            config.generator.clone(),
+            config
+                .generator
+                .generator_type
+                .content_type()
+                .unwrap_or(&config.http.content_type),
+            client_limits.clone(),
+            previous.map(|p| &p.default.generator),
+        )?;
+
+        let mut configured = Vec::with_capacity(config.routes.len());
+        for route in &config.routes {
+            let generator_config = route.generator_config(&config.generator);
+            tracing::info!(
+                "Using generator for route '{}': {}",
+                route.path,
+                generator_config.generator_type
+            );
+            let content_type = route.content_type(&generator_config.generator_type, &config.http);
+            let previous = previous
+                .and_then(|p| p.configured(&route.path))
+                .map(|r| &r.generator);
+            let route_state = RouteState::new(
+                generator_config,
+                content_type,
+                client_limits.clone(),
+                previous,
+            )?;
+            configured.push((route.path.clone(), route_state));
+        }
+
+        Ok(Self {
+            default,
+            configured,
+            client_limits,
+        })
     }
 
-    if config.generator.chunk_buffer < 1 {
-        eprintln!("generator.chunk_buffer must be >= 1");
-        return Err(error_code::GENERATOR_CHUNK_BUFFER_TOO_SMALL);
+    /// The route from `[[routes]]` with `path`, if there is one.
+    fn configured(&self, path: &str) -> Option<&RouteState> {
+        self.configured
+            .iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, route_state)| route_state)
     }
 
-    // Create gen depending on config
-    tracing::info!("Using generator: {}", config.generator.generator_type);
-    let gen_strategy = match &config.generator.generator_type {
-        GeneratorType::Random => {
-            GeneratorStrategyContainer::Random(Random::new(config.generator.chunk_size))
+    /// Creates a router serving either the routes given by `paths`, or the ones in the config
+    /// if `None`.
+    fn router(&self, config: &Config, paths: Option<&[String]>) -> Router {
+        let default_route = self.default.clone();
+        let handler = move |context: RequestContext| text_stream(default_route, context);
+
+        let mut app = Router::new();
+
+        if let Some(paths) = paths {
+            // Only the listed routes are served, using their own settings if they have any
+            for path in paths {
+                let route_state = self.configured(path).unwrap_or(&self.default).clone();
+                app = app.route(
+                    path,
+                    on(ANY_METHOD, move |context: RequestContext| {
+                        text_stream(route_state, context)
+                    }),
+                );
+            }
+            tracing::info!("Listening on routes: {}", paths.join(", "));
+            return add_layers(app, config);
         }
-        GeneratorType::MarkovChain(input) => GeneratorStrategyContainer::MarkovChain(
-            MarkovChain::new(config.generator.chunk_size, input),
-        ),
-        GeneratorType::Static(input) => GeneratorStrategyContainer::Static(Static::new(input)),
-    };
-    let generator_confg = Arc::new(config.generator.clone());
-    let generator = Generator::from_config(generator_confg);
 
-    let content_type = config.http.content_type.parse().map_err(|e| {
-        eprintln!(
-            "cannot parse content_type '{}' to valid header due to error: {e}",
-            config.http.content_type
-        );
-        error_code::BAD_CONTENT_TYPE
-    })?;
-    let handler = move || text_stream(content_type, generator, gen_strategy);
-
-    let mut app = Router::new();
-    if config.http.catch_all {
-        // Since we have no other routes now, all will be passed to the fallback
-        app = app.fallback(on(ANY_METHOD, handler));
-        tracing::info!("Catch-All enabled");
-    } else if !config.http.routes.is_empty() {
-        for route in &config.http.routes {
-            let handler = handler.clone();
-            app = app.route(route, on(ANY_METHOD, handler));
-        }
-        tracing::info!("Listening on routes: {}", config.http.routes.join(", "));
-    } else {
-        eprintln!("http.catch_all was disabled, but no routes was provided!");
-        return Err(error_code::BAD_CONFIG);
+        // Routes with their own settings take precedence over the plain ones
+        for (path, route_state) in &self.configured {
+            let route_state = route_state.clone();
+            app = app.route(
+                path,
+                on(ANY_METHOD, move |context: RequestContext| {
+                    text_stream(route_state, context)
+                }),
+            );
+        }
+
+        if config.http.catch_all {
+            // Any route not configured in `routes` will be passed to the fallback
+            app = app.fallback(on(ANY_METHOD, handler));
+            tracing::info!("Catch-All enabled");
+        } else {
+            let is_configured = |route: &str| self.configured.iter().any(|(p, _)| p == route);
+            for route in &config.http.routes {
+                if is_configured(route) {
+                    continue;
+                }
+                let handler = handler.clone();
+                app = app.route(route, on(ANY_METHOD, handler));
+            }
+            let all_routes: Vec<&str> = config
+                .http
+                .routes
+                .iter()
+                .map(String::as_str)
+                .filter(|r| !is_configured(r))
+                .chain(self.configured.iter().map(|(p, _)| p.as_str()))
+                .collect();
+            tracing::info!("Listening on routes: {}", all_routes.join(", "));
+        }
+
+        add_layers(app, config)
     }
 
+    /// Creates the routers of `listeners`. Every listener gets its own router, but they all
+    /// share the same generators.
+    fn routers(&self, config: &Config, listeners: &[ListenerConfig]) -> Vec<Router> {
+        listeners
+            .iter()
+            .map(|l| self.router(config, l.routes.as_deref()))
+            .collect()
+    }
+}
+
+/// Adds tracing to a router.
+fn add_layers(app: Router, config: &Config) -> Router {
     // Add tracing to as a layer to our app, span must hold some records that we are interested in
     let trace_layer = tower_http::trace::TraceLayer::new_for_http()
         .make_span_with(PandoraRequestSpan)
@@ -152,16 +415,19 @@ fn create_app(config: &Config) -> Result<Router, i32> {
         .on_eos(tower_http::trace::DefaultOnEos::new().level(tracing::Level::DEBUG))
         .on_failure(tower_http::trace::DefaultOnFailure::new().level(tracing::Level::DEBUG));
 
-    app = app.layer(trace_layer);
-
-    // Set rate limiting
+    // The client IP must be known when the request is traced
+    let trusted_proxies = TrustedProxies::new(&config.http.trusted_proxies);
+    app.layer(trace_layer)
+        .layer(MapRequestLayer::new(move |request| {
+            trusted_proxies.add_client_ip(request)
+        }))
+}
 
+/// Adds the rate limit in `http` to the router of a listener. It is not replaced when the config
+/// is reloaded, so that it does not start over.
+fn rate_limited(mut app: Router, http: &HttpConfig) -> Router {
     // u64, so not below zero
-    if config.http.rate_limit != 0 {
-        if config.http.rate_limit_period == 0 {
-            eprintln!("You cannot activate rate limiting and then set the period to 0!");
-            return Err(error_code::BAD_CONFIG);
-        }
+    if http.rate_limit != 0 {
         // See https://github.com/tokio-rs/axum/discussions/987#discussioncomment-2678115
         app = app.layer(
             ServiceBuilder::new()
@@ -173,44 +439,283 @@ fn create_app(config: &Config) -> Result<Router, i32> {
                 }))
                 .layer(BufferLayer::new(1024))
                 .layer(RateLimitLayer::new(
-                    config.http.rate_limit,
-                    Duration::from_secs(config.http.rate_limit_period),
+                    http.rate_limit,
+                    Duration::from_secs(http.rate_limit_period),
                 )),
         );
     };
 
-    Ok(app)
+    app
+}
+
+/// Creates a new app from a config, like the one served on listeners without routes of their
+/// own.
+///
+/// Returns the exit code of the first problem in case of configuration errors.
+#[cfg(test)]
+fn create_app(config: &Config) -> Result<Router, i32> {
+    if let Some(problem) = check::check(config).first() {
+        return Err(problem.code);
+    }
+    let app = Routes::new(config)?.router(config, None);
+    Ok(rate_limited(app, &config.http))
+}
+
+/// Reads the config at `path` again, and replaces `routers` of the running `listeners` with
+/// ones using it, keeping the limits of `routes` where they are unchanged. Listeners, TLS,
+/// logging and `http.rate_limit` cannot change without a restart, so only their routes are
+/// updated. Unknown keys are only allowed if the config is not `strict`.
+///
+/// Returns the new config and routes, or `None` if the config could not be used, in which case
+/// the routers are kept as they are.
+fn reload_config(
+    path: &Path,
+    strict: bool,
+    config: &Config,
+    listeners: &[ListenerConfig],
+    health_listeners: &[ListenerConfig],
+    routes: &Routes,
+    routers: &[ReloadableRouter],
+) -> Option<(Config, Routes)> {
+    tracing::info!("Reloading config from '{}'", path.to_string_lossy());
+    let new_config = match Config::from_path(path, strict) {
+        Ok((c, unknown_keys)) => {
+            for key in unknown_keys {
+                tracing::warn!("Ignoring {key}");
+            }
+            c
+        }
+        Err(e) => {
+            tracing::error!("Keeping the current config, as {e}");
+            return None;
+        }
+    };
+    let problems = check::check(&new_config);
+    if !problems.is_empty() {
+        for problem in problems {
+            tracing::error!("Reloaded config is invalid: {problem}");
+        }
+        tracing::error!("Keeping the current config");
+        return None;
+    }
+
+    let (new_listeners, new_health_listeners) =
+        resolve_listeners(&new_config.http).expect("listeners are checked");
+    // Listeners are running on an address, so their routes are matched using it
+    let same_listeners = |new: &[ListenerConfig], running: &[ListenerConfig]| {
+        new.len() == running.len()
+            && new.iter().all(|n| {
+                running.iter().any(|r| {
+                    ListenerConfig {
+                        routes: n.routes.clone(),
+                        ..r.clone()
+                    } == *n
+                })
+            })
+    };
+    if !same_listeners(&new_listeners, listeners)
+        || !same_listeners(&new_health_listeners, health_listeners)
+        || new_config.http.tls != config.http.tls
+        || new_config.logging != config.logging
+        || new_config.http.rate_limit != config.http.rate_limit
+        || new_config.http.rate_limit_period != config.http.rate_limit_period
+    {
+        tracing::warn!(
+            "Changes to listeners, TLS, logging or http.rate_limit are only applied after a restart"
+        );
+    }
+    let listeners: Vec<ListenerConfig> = listeners
+        .iter()
+        .map(|running| {
+            new_listeners
+                .iter()
+                .find(|l| l.address == running.address)
+                .unwrap_or(running)
+                .clone()
+        })
+        .collect();
+
+    let new_routes = routes
+        .reload(&new_config)
+        .inspect_err(|code| {
+            tracing::error!(
+                "Reloaded config is invalid (error code {code}), keeping the current one"
+            );
+        })
+        .ok()?;
+    for (router, new_router) in routers
+        .iter()
+        .zip(new_routes.routers(&new_config, &listeners))
+    {
+        router.replace(new_router);
+    }
+    tracing::info!("Reloaded config, new connections will use it");
+    Some((new_config, new_routes))
+}
+
+/// Creates the router used for health checks, that also serves metrics on `/metrics`.
+fn health_router() -> Router {
+    Router::new()
+        .route(
+            "/metrics",
+            get(|| async {
+                (
+                    [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+                    metrics::METRICS.render(),
+                )
+            }),
+        )
+        // Use fallback to always respond with the same value
+        .fallback_service(get(|| async { "OK\n" }))
+}
+
+/// Resolves the addresses to serve generated data and health checks on. Falls back to
+/// `http.port` and `http.health_port` on all IPv4 addresses if no addresses are given.
+///
+/// Only fails if a port is invalid, the listeners are checked by [`check::check`].
+fn resolve_listeners(
+    config: &HttpConfig,
+) -> Result<(Vec<ListenerConfig>, Vec<ListenerConfig>), Problem> {
+    let port_listener = |name: &str, port: &str| {
+        format!("0.0.0.0:{port}")
+            .parse::<SocketAddr>()
+            .map(|addr| ListenerConfig::new(ListenAddress::Tcp(addr)))
+            .map_err(|_| {
+                Problem::new(
+                    error_code::BAD_CONFIG,
+                    format!("http.{name} '{port}' is not a valid port!"),
+                )
+            })
+    };
+
+    let listeners = if config.listen.is_empty() {
+        vec![port_listener("port", &config.port)?]
+    } else {
+        config.listen.clone()
+    };
+
+    let health_listeners = if !config.health_listen.is_empty() {
+        config.health_listen.clone()
+    } else if config.health_port_enabled {
+        vec![port_listener("health_port", &config.health_port)?]
+    } else {
+        Vec::new()
+    };
+
+    Ok((listeners, health_listeners))
+}
+
+/// Reads the config at the default path, using a default config if there is none. Exits if
+/// there is a config that cannot be used, such as one with unknown keys if `strict` is set.
+///
+/// The default path is returned even if there is no config there yet, so that one can be
+/// created and loaded on SIGHUP.
+fn read_default_config(strict: bool) -> (Config, Option<PathBuf>) {
+    let Some(path) = Config::default_path() else {
+        eprintln!("Could not find home directory and config, using default config instead...");
+        return (Config::default(), None);
+    };
+
+    let config = match Config::from_path(&path, strict) {
+        Ok((config, unknown_keys)) => {
+            eprintln!("Using default config at '{}'", path.to_string_lossy());
+            for key in unknown_keys {
+                eprintln!("Ignoring {key}");
+            }
+            config
+        }
+        Err(ConfigError::NotFound(_)) => {
+            eprintln!(
+                "No config found at '{}', using a default instead...",
+                path.to_string_lossy(),
+            );
+            Config::default()
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            exit(e.exit_code());
+        }
+    };
+    (config, Some(path))
+}
+
+/// Binds a listener, exiting if that is not possible.
+async fn bind(config: &ListenerConfig) -> BoundListener {
+    match listener::bind(config).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!(
+                "failed to listen on {} due to error:\n\t{e}",
+                config.address
+            );
+            exit(error_code::CANNOT_BIND);
+        }
+    }
+}
+
+/// Serves `app` on a bound listener, exiting if that is not possible.
+fn serve(
+    servers: &mut JoinSet<std::io::Result<()>>,
+    listener: BoundListener,
+    config: &ListenerConfig,
+    app: Router,
+    #[cfg(feature = "tls")] tls: Option<&tokio_rustls::TlsAcceptor>,
+) {
+    let result = listener::serve(
+        servers,
+        listener,
+        config,
+        app,
+        #[cfg(feature = "tls")]
+        tls,
+    );
+    if let Err(e) = result {
+        eprintln!(
+            "failed to listen on {} due to error:\n\t{e}",
+            config.address
+        );
+        exit(error_code::CANNOT_BIND);
+    }
 }
 
 #[tokio::main]
 async fn main() {
     let pargs = pico_args::Arguments::from_env();
-    let config: Config = match parse_args(pargs, &mut std::io::stdout()) {
-        Ok(Some(config)) => config,
-        Ok(None) => Config::read_from_default_path()
-            .inspect(|_| {
-                eprintln!(
-                    "Using default config at '{}'",
-                    // unwrap is safe, we did read the config after all
-                    Config::default_path().unwrap().to_string_lossy()
-                );
-            })
-            .unwrap_or_else(|| {
-                if let Some(pb) = Config::default_path() {
-                    eprintln!(
-                        "No config found at '{}', using a default instead...",
-                        pb.to_string_lossy(),
-                    );
-                    Config::default()
-                } else {
-                    eprintln!(
-                        "Could not find home directory and config, using default config instead..."
-                    );
-                    Config::default()
-                }
-            }),
+    let args = match parse_args(pargs, &mut std::io::stdout()) {
+        Ok(args) => args,
         Err(code) => exit(code),
     };
+    let (config, config_path): (Config, Option<PathBuf>) = match args.config {
+        Some((config, path)) => (config, Some(path)),
+        None => read_default_config(args.strict),
+    };
+
+    if args.command == Command::CheckConfig {
+        let mut problems = check::check(&config);
+        problems.extend(check::check_startup(&config));
+        match check::report(&problems) {
+            Ok(()) => {
+                println!("Config is valid");
+                exit(0);
+            }
+            Err(code) => exit(code),
+        }
+    }
+    if let Command::Sample(sample_args) = &args.command {
+        match sample::sample(&config, sample_args, &mut std::io::stdout()).await {
+            Ok(()) => exit(0),
+            Err(code) => exit(code),
+        }
+    }
+    if let Command::Bench(bench_args) = &args.command {
+        match bench::bench(&config, bench_args, &mut std::io::stdout()).await {
+            Ok(()) => exit(0),
+            Err(code) => exit(code),
+        }
+    }
+    if let Err(code) = check::report(&check::check(&config)) {
+        exit(code);
+    }
 
     // Set up tracing
     let (pretty, ugly) = if config.logging.no_stdout {
@@ -250,58 +755,141 @@ async fn main() {
         env!("CARGO_PKG_VERSION")
     );
 
-    let app = match create_app(&config) {
-        Ok(a) => a,
-        Err(code) => exit(code),
-    };
-
-    if config.http.health_port_enabled {
-        if config.http.port == config.http.health_port {
-            eprintln!(
-                "Health port and normal port cannot be the same! (Both are {})",
-                config.http.port
-            );
-            exit(error_code::BAD_CONFIG);
-        }
+    let (listeners, health_listeners) =
+        resolve_listeners(&config.http).expect("listeners are checked");
+
+    let mut routes = Routes::new(&config).unwrap_or_else(|code| exit(code));
+    let routers: Vec<ReloadableRouter> = routes
+        .routers(&config, &listeners)
+        .into_iter()
+        .map(ReloadableRouter::new)
+        .collect();
+
+    #[cfg(feature = "tls")]
+    let tls_acceptor = config.http.tls.as_ref().map(|tls_config| {
+        tracing::info!("Using TLS");
+        tls::acceptor(tls_config).unwrap_or_else(|code| exit(code))
+    });
+
+    let mut servers = JoinSet::new();
+
+    for health_listener_config in &health_listeners {
+        let health_router = health_router();
+        let health_listener = bind(health_listener_config).await;
+        tracing::info!(
+            "Health check listening on {}",
+            health_listener_config.address
+        );
+        serve(
+            &mut servers,
+            health_listener,
+            health_listener_config,
+            health_router,
+            #[cfg(feature = "tls")]
+            None,
+        );
+    }
 
-        // Use fallback to always respond with the same value
-        let health_router = Router::new().fallback_service(get(|| async { "OK\n" }));
-        let health_listener = TcpListener::bind(format!("0.0.0.0:{}", config.http.health_port))
-            .await
-            .unwrap();
-        tracing::info!("Health check listening on port {}", config.http.health_port);
-        tokio::spawn(async move { axum::serve(health_listener, health_router).await.unwrap() });
+    for (listener_config, router) in listeners.iter().zip(&routers) {
+        let listener = bind(listener_config).await;
+        tracing::info!("Listening on {}", listener_config.address);
+        serve(
+            &mut servers,
+            listener,
+            listener_config,
+            rate_limited(router.router(), &config.http),
+            #[cfg(feature = "tls")]
+            tls_acceptor.as_ref(),
+        );
     }
 
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", config.http.port))
-        .await
-        .unwrap();
-    tracing::info!("Listening on port {}", config.http.port);
+    let mut hangups = Hangups::new();
+    let shutdown_signal = shutdown::signal();
+    tokio::pin!(shutdown_signal);
+    let mut drain_period = config.http.drain_period;
+    loop {
+        tokio::select! {
+            // Servers only stop by themselves if something has gone horribly wrong
+            result = servers.join_next() => {
+                // There is always at least one server
+                result.unwrap().unwrap().unwrap();
+                break;
+            }
+            () = &mut shutdown_signal => break,
+            () = hangups.recv() => {
+                let Some(path) = config_path.clone() else {
+                    tracing::warn!("Cannot reload config, as there is no path to read it from");
+                    continue;
+                };
+                // Generators can take a while to create, so other tasks must not wait for them
+                let reload = tokio::task::spawn_blocking({
+                    let strict = args.strict;
+                    let config = config.clone();
+                    let listeners = listeners.clone();
+                    let health_listeners = health_listeners.clone();
+                    let routes = routes.clone();
+                    let routers = routers.clone();
+                    move || {
+                        reload_config(
+                            &path,
+                            strict,
+                            &config,
+                            &listeners,
+                            &health_listeners,
+                            &routes,
+                            &routers,
+                        )
+                    }
+                });
+                if let Some((new_config, new_routes)) = reload.await.unwrap() {
+                    drain_period = new_config.http.drain_period;
+                    routes = new_routes;
+                }
+            }
+        }
+    }
+    shutdown::drain(servers, Duration::from_secs(drain_period)).await;
 
-    axum::serve(listener, app).await.unwrap();
+    let (streams, bytes_sent) = metrics::METRICS.totals();
+    tracing::info!(
+        streams,
+        bytes_sent,
+        "Shut down after sending {:.2} GB over {streams} streams",
+        (bytes_sent as f64) * 1e-9
+    );
 }
 
 #[cfg(test)]
 mod tests {
     use std::{
         io::Write,
+        net::SocketAddr,
         time::{self, Duration},
     };
 
     use axum::{
         body::Body,
-        extract::Request,
-        http::{header::CONTENT_TYPE, HeaderMap, Method, StatusCode},
+        extract::{ConnectInfo, Request},
+        http::{
+            header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, RETRY_AFTER},
+            HeaderMap, HeaderValue, Method, StatusCode,
+        },
         Router,
     };
+    use bytes::Bytes;
     use tempfile::NamedTempFile;
     use tokio_stream::StreamExt;
     use tower::ServiceExt; // `oneshot`
 
     use crate::{
-        config::{Config, GeneratorType},
+        check,
+        config::{Config, ContentEncoding, GeneratorType, ListenerConfig},
         create_app, error_code,
         generator::P_TAG_SIZE,
+        health_router,
+        listener::ConnectionInfo,
+        reload::ReloadableRouter,
+        reload_config, resolve_listeners, Routes,
     };
 
     /// Tests if an app responds with what seems like an infinite stream on
@@ -453,6 +1041,451 @@ mod tests {
         }
     }
 
+    async fn content_type(app: Router) -> HeaderValue {
+        let response = app
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        response.headers()[CONTENT_TYPE].clone()
+    }
+
+    #[tokio::test]
+    async fn reload_config_replaces_routers() {
+        let config_file = NamedTempFile::new().unwrap();
+        let mut config = Config::default();
+        let (listeners, health_listeners) = resolve_listeners(&config.http).unwrap();
+        let routes = Routes::new(&config).unwrap();
+        let routers: Vec<ReloadableRouter> = routes
+            .routers(&config, &listeners)
+            .into_iter()
+            .map(ReloadableRouter::new)
+            .collect();
+        let app = routers[0].router();
+        assert_eq!(content_type(app.clone()).await, "text/html; charset=utf-8");
+
+        // Invalid configs are not used
+        config.http.content_type = "text/plain".to_string();
+        config.generator.chunk_buffer = 0;
+        std::fs::write(config_file.path(), toml::to_string(&config).unwrap()).unwrap();
+        let reloaded = reload_config(
+            config_file.path(),
+            false,
+            &Config::default(),
+            &listeners,
+            &health_listeners,
+            &routes,
+            &routers,
+        );
+        assert!(reloaded.is_none());
+        assert_eq!(content_type(app.clone()).await, "text/html; charset=utf-8");
+
+        config.generator.chunk_buffer = 1;
+        std::fs::write(config_file.path(), toml::to_string(&config).unwrap()).unwrap();
+        let reloaded = reload_config(
+            config_file.path(),
+            false,
+            &Config::default(),
+            &listeners,
+            &health_listeners,
+            &routes,
+            &routers,
+        );
+        assert_eq!(reloaded.map(|(c, _)| c), Some(config));
+        assert_eq!(content_type(app).await, "text/plain");
+    }
+
+    #[tokio::test]
+    async fn reload_keeps_unchanged_limits() {
+        let config = |max_concurrent: usize, rate_limit: usize| -> Config {
+            toml::from_str(&format!(
+                "[generator]\n\
+                 max_concurrent = {max_concurrent}\n\
+                 overflow_policy = {{ name = 'service_unavailable', data = 1 }}\n\
+                 [http.client_limits]\n\
+                 rate_limit = {rate_limit}"
+            ))
+            .unwrap()
+        };
+        let ip = Some("192.0.2.1".parse().unwrap());
+        let routes = Routes::new(&config(1, 1)).unwrap();
+        let _permit = routes.default.generator.acquire_permit().await.unwrap();
+        routes.client_limits.admit(ip).await.unwrap();
+
+        // Streams that are already running still count towards the limits
+        let mut reloaded_config = config(1, 1);
+        reloaded_config.http.content_type = "text/plain".to_string();
+        let reloaded = routes.reload(&reloaded_config).unwrap();
+        assert!(reloaded.default.generator.acquire_permit().await.is_none());
+        assert!(reloaded.client_limits.admit(ip).await.is_err());
+
+        // Changed limits start over
+        let reloaded = reloaded.reload(&config(2, 2)).unwrap();
+        assert!(reloaded.default.generator.acquire_permit().await.is_some());
+        reloaded.client_limits.admit(ip).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn app_with_route_config() {
+        let msg = "SECRET_KEY=hunter2\n".to_string();
+        let mut tmpfile: NamedTempFile = tempfile::NamedTempFile::new().unwrap();
+        let _ = tmpfile.write(msg.as_bytes()).unwrap();
+
+        let toml_str = format!(
+            r#"
+            [http]
+            catch_all = false
+            routes = ["/wp-login.php"]
+
+            [[routes]]
+            path = "/.env"
+            type = {{ name = "static", data = "{}" }}
+            prefix = ""
+            content_type = "text/plain"
+            "#,
+            tmpfile.path().to_string_lossy()
+        );
+        let config: Config = toml::from_str(&toml_str).unwrap();
+        let app = create_app(&config).unwrap();
+
+        // Plain routes are still served using the global settings
+        assert!(app_responds_on_uri(app.clone(), "/wp-login.php").await);
+        assert!(!app_responds_on_uri(app.clone(), "/").await);
+
+        let response = app
+            .oneshot(Request::builder().uri("/.env").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/plain");
+
+        let mut body = response.into_body().into_data_stream();
+        for _ in 0..100 {
+            let chunk = body.next().await.unwrap().unwrap();
+            assert_eq!(chunk, msg);
+        }
+    }
+
+    /// Returns the first chunk sent on an URI.
+    async fn first_chunk(app: Router, uri: &str) -> Bytes {
+        let response = app
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body().into_data_stream();
+        body.next().await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn app_with_link_maze_generator() {
+        let mut config = Config::default();
+        config.generator.generator_type = GeneratorType::LinkMaze("/maze/".to_string());
+        let app = create_app(&config).unwrap();
+
+        let first = first_chunk(app.clone(), "/maze/abc").await;
+        let text = String::from_utf8(first.to_vec()).unwrap();
+        assert!(
+            text.contains("<a href=\"/maze/"),
+            "no links under prefix in '{text}'"
+        );
+
+        // Revisiting should give the exact same page, but other pages should differ
+        assert_eq!(first, first_chunk(app.clone(), "/maze/abc").await);
+        assert_ne!(first, first_chunk(app, "/maze/abd").await);
+    }
+
+    #[tokio::test]
+    async fn app_with_json_generator() {
+        let mut config = Config::default();
+        config.generator.generator_type = GeneratorType::Json;
+        config.generator.size_limit = 1;
+        let app = create_app(&config).unwrap();
+
+        let response = app
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        let mut stream = response.into_body().into_data_stream();
+        let mut body = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            body.extend(chunk.unwrap());
+        }
+        let array: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert!(!array.is_empty());
+    }
+
+    #[tokio::test]
+    async fn app_with_feed_generator() {
+        let toml_str = r#"
+            [generator]
+            size_limit = 1
+
+            [[routes]]
+            path = "/sitemap.xml"
+            type = { name = "feed", data = { format = "sitemap", links = "/maze" } }
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let app = create_app(&config).unwrap();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/sitemap.xml")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            "application/xml; charset=utf-8"
+        );
+        let mut stream = response.into_body().into_data_stream();
+        let mut body = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            body.extend(chunk.unwrap());
+        }
+        // Neither the HTML prefix nor suffix is used
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.starts_with("<?xml"), "not XML: '{body}'");
+        assert!(body.ends_with("</urlset>\n"));
+    }
+
+    #[tokio::test]
+    async fn app_with_compression_bomb() {
+        let mut config = Config::default();
+        config.generator.compression_bomb = vec![ContentEncoding::Gzip];
+        config.generator.size_limit = 1;
+        let app = create_app(&config).unwrap();
+
+        // Clients that do not accept the encoding get the usual treatment
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header(ACCEPT_ENCODING, "gzip, deflate")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            config.http.content_type.as_str()
+        );
+
+        // Starts with a gzip header, and limits still apply
+        let mut body = response.into_body().into_data_stream();
+        let first = body.next().await.unwrap().unwrap();
+        assert!(first.starts_with(&[0x1f, 0x8b]));
+        assert!(body.next().await.is_none(), "size limit was not respected");
+    }
+
+    #[tokio::test]
+    async fn app_with_listener_routes() {
+        let toml_str = r#"
+            [http]
+            listen = [
+                "127.0.0.1:8080",
+                { address = "127.0.0.1:8443", routes = ["/wp-login.php", "/.env"] },
+            ]
+
+            [[routes]]
+            path = "/.env"
+            content_type = "text/plain"
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let routes = Routes::new(&config).unwrap();
+
+        // Listeners without routes of their own use the global settings
+        let app = routes.router(&config, None);
+        assert!(app_responds_on_uri(app.clone(), "/").await);
+        assert!(app_responds_on_uri(app, "/.env").await);
+
+        let app = routes.router(&config, config.http.listen[1].routes.as_deref());
+        assert!(app_responds_on_uri(app.clone(), "/wp-login.php").await);
+        assert!(!app_responds_on_uri(app.clone(), "/").await);
+        let response = app
+            .oneshot(Request::builder().uri("/.env").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/plain");
+    }
+
+    #[tokio::test]
+    async fn health_router_serves_metrics() {
+        let app = health_router();
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("# TYPE pandoras_pot_active_streams gauge"));
+    }
+
+    #[tokio::test]
+    async fn app_with_overflow_policy() {
+        let toml_str = r#"
+            [generator]
+            max_concurrent = 1
+            overflow_policy = { name = "service_unavailable", data = 60 }
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let app = create_app(&config).unwrap();
+
+        let request = || Request::builder().uri("/").body(Body::empty()).unwrap();
+        let streaming = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(streaming.status(), StatusCode::OK);
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "60");
+
+        let toml_str = r#"
+            [generator]
+            overflow_policy = { name = "tarpit", data = 0 }
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(create_app(&config).unwrap_err(), error_code::BAD_CONFIG);
+    }
+
+    #[tokio::test]
+    async fn app_with_client_limits() {
+        let mut config = Config::default();
+        config.http.client_limits.max_concurrent = 1;
+        let app = create_app(&config).unwrap();
+
+        let request = |peer: &str| {
+            let mut request = Request::builder().uri("/").body(Body::empty()).unwrap();
+            let peer: SocketAddr = peer.parse().unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(ConnectionInfo::from(peer)));
+            request
+        };
+
+        let streaming = app
+            .clone()
+            .oneshot(request("192.0.2.1:4711"))
+            .await
+            .unwrap();
+        assert_eq!(streaming.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(request("192.0.2.1:4712"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let response = app
+            .clone()
+            .oneshot(request("192.0.2.2:4711"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The stream ends when the body is dropped
+        drop(streaming);
+        let response = app.oneshot(request("192.0.2.1:4713")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        config.http.client_limits.max_clients = 0;
+        assert_eq!(create_app(&config).unwrap_err(), error_code::BAD_CONFIG);
+    }
+
+    fn problem_codes(config: &Config) -> Vec<i32> {
+        check::check(config).iter().map(|p| p.code).collect()
+    }
+
+    #[test]
+    fn listen_addresses() {
+        let mut config = Config::default();
+        let (listeners, health_listeners) = resolve_listeners(&config.http).unwrap();
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].address, "0.0.0.0:8080".parse().unwrap());
+        assert_eq!(listeners[0].routes, None);
+        assert!(health_listeners.is_empty());
+
+        config.http.health_port_enabled = true;
+        let (_, health_listeners) = resolve_listeners(&config.http).unwrap();
+        assert_eq!(health_listeners[0].address, "0.0.0.0:8081".parse().unwrap());
+
+        // The same address cannot be used twice, even by the health check
+        config.http.health_port = config.http.port.clone();
+        assert_eq!(problem_codes(&config), [error_code::BAD_CONFIG]);
+
+        config.http.health_port_enabled = false;
+        config.http.port = "http".to_string();
+        assert_eq!(
+            resolve_listeners(&config.http).unwrap_err().code,
+            error_code::BAD_CONFIG
+        );
+
+        // Ports are not used at all if addresses are given
+        let mut listener = ListenerConfig::new("[::1]:8080".parse().unwrap());
+        listener.routes = Some(vec![]);
+        config.http.listen = vec![listener];
+        assert_eq!(
+            problem_codes(&config),
+            [error_code::BAD_CONFIG],
+            "listener without routes was accepted"
+        );
+        config.http.listen[0].routes = None;
+        let (listeners, _) = resolve_listeners(&config.http).unwrap();
+        assert_eq!(listeners, config.http.listen);
+
+        // Health checks are served the same way on all routes
+        let mut health_listener = ListenerConfig::new("unix:/tmp/health.sock".parse().unwrap());
+        health_listener.routes = Some(vec!["/".to_string()]);
+        config.http.health_listen = vec![health_listener];
+        assert_eq!(
+            problem_codes(&config),
+            [error_code::BAD_CONFIG],
+            "health check listener with routes was accepted"
+        );
+    }
+
+    #[test]
+    fn app_duplicate_route_config() {
+        let toml_str = r#"
+            [[routes]]
+            path = "/.env"
+
+            [[routes]]
+            path = "/.env"
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        match create_app(&config) {
+            Ok(_) => panic!("app created although a route was configured twice"),
+            Err(code) => assert_eq!(code, error_code::BAD_CONFIG),
+        }
+    }
+
     #[test]
     fn app_disabled_catch_all_no_routes() {
         let mut config = Config::default();
@@ -0,0 +1,343 @@
+//! Metrics about the misery we deal out, served in the Prometheus text format on the health
+//! listeners.
+//!
+//! Everything is collected in the global [`METRICS`], since generators are created in many
+//! places and there is only one process to describe anyway.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
+};
+
+use tokio::{sync::Semaphore, time::Instant};
+
+/// Metrics of the whole process.
+pub(crate) static METRICS: Metrics = Metrics::new();
+
+/// Upper bounds of the buckets of the stream duration histogram, in seconds.
+const DURATION_BUCKETS: [u64; 9] = [1, 5, 10, 30, 60, 300, 900, 3600, 4 * 3600];
+
+/// Why a stream ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EndReason {
+    /// The client stopped listening.
+    ClientClosed,
+    /// `generator.time_limit` was reached.
+    TimeLimit,
+    /// `generator.size_limit` was reached.
+    SizeLimit,
+    /// The generator strategy stopped producing data.
+    GeneratorEnded,
+    /// We are shutting down, and `http.drain_period` is over.
+    Shutdown,
+}
+
+impl EndReason {
+    const ALL: [Self; 5] = [
+        Self::ClientClosed,
+        Self::TimeLimit,
+        Self::SizeLimit,
+        Self::GeneratorEnded,
+        Self::Shutdown,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ClientClosed => "client_closed",
+            Self::TimeLimit => "time_limit",
+            Self::SizeLimit => "size_limit",
+            Self::GeneratorEnded => "generator_ended",
+            Self::Shutdown => "shutdown",
+        }
+    }
+}
+
+/// Counters for a single kind of generator strategy.
+#[derive(Debug, Default)]
+struct GeneratorMetrics {
+    streams: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+#[derive(Debug)]
+pub(crate) struct Metrics {
+    active_streams: AtomicU64,
+    ended: [AtomicU64; EndReason::ALL.len()],
+    /// Count of streams in each of [`DURATION_BUCKETS`], and then one for longer streams.
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    duration_sum_millis: AtomicU64,
+    generators: Mutex<BTreeMap<&'static str, Arc<GeneratorMetrics>>>,
+    /// Permits of all generators with a limited `max_concurrent`. Generators are forgotten once
+    /// they are dropped.
+    permits: Mutex<Vec<Weak<Semaphore>>>,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            active_streams: AtomicU64::new(0),
+            ended: [const { AtomicU64::new(0) }; EndReason::ALL.len()],
+            duration_buckets: [const { AtomicU64::new(0) }; DURATION_BUCKETS.len() + 1],
+            duration_sum_millis: AtomicU64::new(0),
+            generators: Mutex::new(BTreeMap::new()),
+            permits: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Includes the available permits of `permits` in the metrics, for as long as it exists.
+    pub fn register_permits(&self, permits: &Arc<Semaphore>) {
+        let mut all_permits = self.permits.lock().unwrap();
+        all_permits.retain(|permits| permits.strong_count() > 0);
+        all_permits.push(Arc::downgrade(permits));
+    }
+
+    /// Stops including `permits` in the metrics, for when its generator was replaced on reload but
+    /// still exists because of the streams it is sending.
+    pub fn forget_permits(&self, permits: &Arc<Semaphore>) {
+        self.permits
+            .lock()
+            .unwrap()
+            .retain(|p| p.strong_count() > 0 && !std::ptr::eq(p.as_ptr(), Arc::as_ptr(permits)));
+    }
+
+    /// Starts counting a new stream, generated using the strategy called `generator`.
+    pub fn stream_started(&'static self, generator: &'static str) -> StreamMetrics {
+        let generator_metrics = self
+            .generators
+            .lock()
+            .unwrap()
+            .entry(generator)
+            .or_default()
+            .clone();
+        generator_metrics.streams.fetch_add(1, Ordering::Relaxed);
+        self.active_streams.fetch_add(1, Ordering::Relaxed);
+        StreamMetrics {
+            metrics: self,
+            generator: generator_metrics,
+            start_time: Instant::now(),
+        }
+    }
+
+    /// Streams currently being sent.
+    pub fn active_streams(&self) -> u64 {
+        self.active_streams.load(Ordering::Relaxed)
+    }
+
+    /// Total amount of streams started, and bytes sent by them.
+    pub fn totals(&self) -> (u64, u64) {
+        self.generators
+            .lock()
+            .unwrap()
+            .values()
+            .fold((0, 0), |(streams, bytes_sent), metrics| {
+                (
+                    streams + metrics.streams.load(Ordering::Relaxed),
+                    bytes_sent + metrics.bytes_sent.load(Ordering::Relaxed),
+                )
+            })
+    }
+
+    fn stream_ended(&self, reason: EndReason, duration: Duration) {
+        self.active_streams.fetch_sub(1, Ordering::Relaxed);
+        self.ended[reason as usize].fetch_add(1, Ordering::Relaxed);
+
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|&bound| duration <= Duration::from_secs(bound))
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        let millis = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+        self.duration_sum_millis
+            .fetch_add(millis, Ordering::Relaxed);
+    }
+
+    /// Writes all metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        write_header(
+            &mut out,
+            "pandoras_pot_active_streams",
+            "gauge",
+            "Streams currently being sent.",
+        );
+        let active_streams = self.active_streams();
+        let _ = writeln!(out, "pandoras_pot_active_streams {active_streams}");
+
+        write_header(
+            &mut out,
+            "pandoras_pot_available_permits",
+            "gauge",
+            "Streams that can start before reaching generator.max_concurrent, if it is set.",
+        );
+        let available_permits: usize = self
+            .permits
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|permits| permits.available_permits())
+            .sum();
+        let _ = writeln!(out, "pandoras_pot_available_permits {available_permits}");
+
+        let generators = self.generators.lock().unwrap();
+        write_header(
+            &mut out,
+            "pandoras_pot_streams_total",
+            "counter",
+            "Streams started, by generator.",
+        );
+        for (generator, metrics) in generators.iter() {
+            let streams = metrics.streams.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "pandoras_pot_streams_total{{generator=\"{generator}\"}} {streams}"
+            );
+        }
+        write_header(
+            &mut out,
+            "pandoras_pot_bytes_sent_total",
+            "counter",
+            "Bytes sent, by generator.",
+        );
+        for (generator, metrics) in generators.iter() {
+            let bytes_sent = metrics.bytes_sent.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "pandoras_pot_bytes_sent_total{{generator=\"{generator}\"}} {bytes_sent}"
+            );
+        }
+        drop(generators);
+
+        write_header(
+            &mut out,
+            "pandoras_pot_streams_ended_total",
+            "counter",
+            "Streams ended, by reason.",
+        );
+        for reason in EndReason::ALL {
+            let ended = self.ended[reason as usize].load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "pandoras_pot_streams_ended_total{{reason=\"{}\"}} {ended}",
+                reason.as_str()
+            );
+        }
+
+        write_header(
+            &mut out,
+            "pandoras_pot_stream_duration_seconds",
+            "histogram",
+            "Duration of ended streams.",
+        );
+        let mut count = 0;
+        for (i, bucket) in self.duration_buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let bound = DURATION_BUCKETS
+                .get(i)
+                .map_or_else(|| "+Inf".to_string(), u64::to_string);
+            let _ = writeln!(
+                out,
+                "pandoras_pot_stream_duration_seconds_bucket{{le=\"{bound}\"}} {count}"
+            );
+        }
+        let sum = self.duration_sum_millis.load(Ordering::Relaxed) as f64 / 1000.0;
+        let _ = writeln!(out, "pandoras_pot_stream_duration_seconds_sum {sum}");
+        let _ = writeln!(out, "pandoras_pot_stream_duration_seconds_count {count}");
+
+        out
+    }
+}
+
+/// Writes the `HELP` and `TYPE` lines of a metric.
+fn write_header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    // Writing to a `String` cannot fail
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {metric_type}");
+}
+
+/// Counts what happens to a single stream.
+#[derive(Debug)]
+pub(crate) struct StreamMetrics {
+    metrics: &'static Metrics,
+    generator: Arc<GeneratorMetrics>,
+    start_time: Instant,
+}
+
+impl StreamMetrics {
+    pub fn bytes_sent(&self, bytes: usize) {
+        self.generator
+            .bytes_sent
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn ended(self, reason: EndReason) {
+        self.metrics.stream_ended(reason, self.start_time.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::sync::Semaphore;
+
+    use super::{EndReason, Metrics};
+
+    #[tokio::test(start_paused = true)]
+    async fn renders_metrics() {
+        let metrics: &'static Metrics = Box::leak(Box::new(Metrics::new()));
+        let permits = Arc::new(Semaphore::new(10));
+        metrics.register_permits(&permits);
+        let _permit = permits.try_acquire().unwrap();
+
+        let stream = metrics.stream_started("random");
+        stream.bytes_sent(100);
+        stream.bytes_sent(23);
+        tokio::time::advance(Duration::from_secs(7)).await;
+        stream.ended(EndReason::TimeLimit);
+        let _active = metrics.stream_started("link_maze");
+
+        assert_eq!(metrics.active_streams(), 1);
+        assert_eq!(metrics.totals(), (2, 123));
+
+        let rendered = metrics.render();
+        for line in [
+            "pandoras_pot_active_streams 1",
+            "pandoras_pot_available_permits 9",
+            "pandoras_pot_streams_total{generator=\"random\"} 1",
+            "pandoras_pot_streams_total{generator=\"link_maze\"} 1",
+            "pandoras_pot_bytes_sent_total{generator=\"random\"} 123",
+            "pandoras_pot_streams_ended_total{reason=\"time_limit\"} 1",
+            "pandoras_pot_streams_ended_total{reason=\"client_closed\"} 0",
+            "pandoras_pot_stream_duration_seconds_bucket{le=\"5\"} 0",
+            "pandoras_pot_stream_duration_seconds_bucket{le=\"10\"} 1",
+            "pandoras_pot_stream_duration_seconds_bucket{le=\"+Inf\"} 1",
+            "pandoras_pot_stream_duration_seconds_sum 7",
+            "pandoras_pot_stream_duration_seconds_count 1",
+        ] {
+            assert!(
+                rendered.lines().any(|l| l == line),
+                "'{line}' not in:\n{rendered}"
+            );
+        }
+
+        // Forgotten and dropped generators are not included
+        let other_permits = Arc::new(Semaphore::new(5));
+        metrics.register_permits(&other_permits);
+        metrics.forget_permits(&other_permits);
+        assert!(metrics
+            .render()
+            .contains("pandoras_pot_available_permits 9\n"));
+        drop(_permit);
+        drop(permits);
+        assert!(metrics
+            .render()
+            .contains("pandoras_pot_available_permits 0\n"));
+    }
+}
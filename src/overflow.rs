@@ -0,0 +1,172 @@
+//! Responses for connections that cannot get a generator, because `generator.max_concurrent`
+//! is reached.
+
+use std::{iter, time::Duration};
+
+use axum::{
+    http::{header::RETRY_AFTER, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use futures::{future, Stream, StreamExt};
+use rand::{distr::Alphanumeric, rngs::SmallRng, Rng, SeedableRng};
+use tokio::time::{Instant, MissedTickBehavior};
+use tokio_stream::wrappers::IntervalStream;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    client_limits::ClientPermit,
+    config::{GeneratorConfig, OverflowPolicy},
+    generator::static_strategy::Static,
+    shutdown,
+    stream_body::StreamBody,
+};
+
+/// What to send instead of generated data, created from a [`OverflowPolicy`].
+#[derive(Debug, Clone)]
+pub(crate) enum Overflow {
+    /// Respond with `503 Service Unavailable`, optionally with a `Retry-After` header.
+    Unavailable(Option<u64>),
+    /// Respond with the data of a static generator, once.
+    Static(Static),
+    /// Send this many bytes per second.
+    Tarpit(usize),
+}
+
+impl Overflow {
+    /// Creates the response of a policy, reading any files it uses.
+    ///
+    /// Returns an exit code if a file cannot be read.
+    pub fn new(policy: &OverflowPolicy) -> Result<Self, i32> {
+        Ok(match policy {
+            // Only used when waiting times out
+            OverflowPolicy::Wait(_) => Self::Unavailable(None),
+            OverflowPolicy::ServiceUnavailable(retry_after) => {
+                Self::Unavailable(Some(*retry_after))
+            }
+            OverflowPolicy::Static(path) => Self::Static(Static::new(path)?),
+            OverflowPolicy::Tarpit(bytes_per_second) => Self::Tarpit(*bytes_per_second),
+        })
+    }
+
+    /// The response to send, using `headers` and the limits in `config` for anything that is
+    /// not an error. `permit` is held until the response has been sent.
+    pub fn response(
+        &self,
+        headers: HeaderMap,
+        config: &GeneratorConfig,
+        permit: ClientPermit,
+    ) -> Response {
+        match self {
+            Self::Unavailable(retry_after) => {
+                let mut response = StatusCode::SERVICE_UNAVAILABLE.into_response();
+                if let Some(retry_after) = retry_after {
+                    response
+                        .headers_mut()
+                        .insert(RETRY_AFTER, HeaderValue::from(*retry_after));
+                }
+                response
+            }
+            Self::Static(data) => (headers, data.data()).into_response(),
+            Self::Tarpit(bytes_per_second) => {
+                StreamBody::from_stream(tarpit(*bytes_per_second, config, shutdown::closing()))
+                    .headers(headers)
+                    .permit(permit)
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// An endless stream of `bytes_per_second` bytes each second, starting with `generator.prefix`
+/// and respecting the time and size limits. Ends once `closing` is cancelled.
+fn tarpit(
+    bytes_per_second: usize,
+    config: &GeneratorConfig,
+    closing: CancellationToken,
+) -> impl Stream<Item = Bytes> + use<> {
+    // No need to be secure, we are smacking bots
+    let mut bytes = config
+        .prefix
+        .clone()
+        .into_bytes()
+        .into_iter()
+        .chain(SmallRng::from_os_rng().sample_iter(Alphanumeric));
+
+    // `0` means no limit
+    let chunk_limit = match config.size_limit {
+        0 => usize::MAX,
+        size_limit => size_limit.div_ceil(bytes_per_second),
+    };
+    let time_limit = Duration::from_secs(config.time_limit);
+    let start_time = Instant::now();
+
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    IntervalStream::new(interval)
+        .take(chunk_limit)
+        .take_while(move |_| {
+            future::ready(time_limit.is_zero() || start_time.elapsed() < time_limit)
+        })
+        .take_until(closing.cancelled_owned())
+        .map(move |_| Bytes::from_iter(iter::from_fn(|| bytes.next()).take(bytes_per_second)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::{header::RETRY_AFTER, HeaderMap, StatusCode};
+    use tokio_stream::StreamExt;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        client_limits::ClientPermit,
+        config::{GeneratorConfig, OverflowPolicy},
+    };
+
+    use super::Overflow;
+
+    #[test]
+    fn service_unavailable() {
+        let config = GeneratorConfig::default();
+        let response = Overflow::new(&OverflowPolicy::ServiceUnavailable(60))
+            .unwrap()
+            .response(HeaderMap::new(), &config, ClientPermit::default());
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "60");
+
+        let response = Overflow::new(&OverflowPolicy::Wait(10)).unwrap().response(
+            HeaderMap::new(),
+            &config,
+            ClientPermit::default(),
+        );
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!response.headers().contains_key(RETRY_AFTER));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tarpit() {
+        let mut config = GeneratorConfig::default();
+        config.prefix = "<html>".to_string();
+        config.size_limit = 10;
+
+        let start = tokio::time::Instant::now();
+        let chunks: Vec<_> = super::tarpit(4, &config, CancellationToken::new())
+            .collect()
+            .await;
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0], "<htm");
+        assert!(chunks[1].starts_with(b"l>"));
+        assert!(chunks.iter().all(|chunk| chunk.len() == 4));
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+
+        // Ends when shutting down, regardless of limits
+        config.size_limit = 0;
+        let closing = CancellationToken::new();
+        let mut tarpit = Box::pin(super::tarpit(4, &config, closing.clone()));
+        assert!(tarpit.next().await.is_some());
+        closing.cancel();
+        assert!(tarpit.next().await.is_none());
+    }
+}
@@ -0,0 +1,255 @@
+//! Support for the PROXY protocol, used by load balancers such as `HAProxy` to tell us which
+//! client they are forwarding a connection for. Both versions are described in
+//! <https://www.haproxy.org/download/3.0/doc/proxy-protocol.txt>.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::listener::{ConnectionInfo, Handshake};
+
+/// Every version 2 header starts with this.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Max length of a version 1 header, including the final CRLF.
+const V1_MAX_LENGTH: usize = 107;
+
+/// Reads a PROXY protocol header before a connection is served. Connections without a valid
+/// header are dropped.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ProxyProtocol;
+
+impl<Io: AsyncRead + Unpin + Send + 'static> Handshake<Io> for ProxyProtocol {
+    type Io = Io;
+
+    const NAME: &'static str = "PROXY protocol header";
+
+    async fn handshake(&self, mut io: Io, info: &mut ConnectionInfo) -> io::Result<Self::Io> {
+        info.proxied = read_header(&mut io).await?;
+        Ok(io)
+    }
+}
+
+/// Reads a header of either version, leaving everything after it unread. Returns the address of
+/// the client, which is unknown if the proxy made the connection itself or if the client is not
+/// connected over IP.
+async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<SocketAddr>> {
+    // Enough to tell the versions apart, and shorter than any header
+    let mut start = [0; 8];
+    reader.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE[..start.len()] {
+        read_v2(reader).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(reader, &start).await
+    } else {
+        Err(invalid("no PROXY protocol header"))
+    }
+}
+
+/// Reads the rest of a human-readable version 1 header, such as
+/// `PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n`.
+async fn read_v1<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    start: &[u8],
+) -> io::Result<Option<SocketAddr>> {
+    // Read byte by byte, so that we do not read anything that comes after the header
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(invalid("PROXY protocol v1 header is too long"));
+        }
+        line.push(reader.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("PROXY protocol v1 header is not valid text"))?;
+    parse_v1(line)
+}
+
+fn parse_v1(line: &str) -> io::Result<Option<SocketAddr>> {
+    let mut fields = line.split(' ').skip(1);
+    let is_ipv4 = match fields.next() {
+        Some("TCP4") => true,
+        Some("TCP6") => false,
+        // Anything may follow, and should be ignored
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(invalid("unknown protocol in PROXY protocol v1 header")),
+    };
+
+    let (Some(source), Some(_destination), Some(source_port), Some(_destination_port), None) = (
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+    ) else {
+        return Err(invalid(
+            "wrong number of fields in PROXY protocol v1 header",
+        ));
+    };
+
+    let ip: IpAddr = source
+        .parse()
+        .map_err(|_| invalid("invalid address in PROXY protocol v1 header"))?;
+    let port = source_port
+        .parse()
+        .map_err(|_| invalid("invalid port in PROXY protocol v1 header"))?;
+    if ip.is_ipv4() != is_ipv4 {
+        return Err(invalid("wrong address family in PROXY protocol v1 header"));
+    }
+    Ok(Some(SocketAddr::new(ip, port)))
+}
+
+/// Reads the rest of a binary version 2 header.
+async fn read_v2<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut rest = [0; 8];
+    reader.read_exact(&mut rest).await?;
+    let [signature @ .., version_command, family, len_high, len_low] = rest;
+    if signature[..] != V2_SIGNATURE[8..] {
+        return Err(invalid("invalid PROXY protocol v2 signature"));
+    }
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+
+    // Always read the addresses, so that the connection is left at the start of the request
+    let mut addresses = vec![0; u16::from_be_bytes([len_high, len_low]).into()];
+    reader.read_exact(&mut addresses).await?;
+
+    match version_command & 0x0f {
+        // LOCAL, such as health checks from the proxy itself
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        _ => return Err(invalid("unknown PROXY protocol v2 command")),
+    }
+
+    // Addresses are followed by the destination and ports, and possibly extensions that we do
+    // not care about
+    match family >> 4 {
+        // AF_INET
+        0x1 if addresses.len() >= 12 => {
+            let ip: [u8; 4] = addresses[0..4].try_into().expect("slice has right length");
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port)))
+        }
+        // AF_INET6
+        0x2 if addresses.len() >= 36 => {
+            let ip: [u8; 16] = addresses[0..16].try_into().expect("slice has right length");
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+        0x1 | 0x2 => Err(invalid("PROXY protocol v2 addresses are too short")),
+        // AF_UNSPEC or AF_UNIX, where there is nothing worth knowing
+        _ => Ok(None),
+    }
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, net::SocketAddr};
+
+    use super::{read_header, V2_SIGNATURE};
+
+    /// Reads a header, and returns the result and whatever was left unread.
+    async fn read(mut data: &[u8]) -> (io::Result<Option<SocketAddr>>, &[u8]) {
+        let result = read_header(&mut data).await;
+        (result, data)
+    }
+
+    fn v2_header(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x20 | command, family]);
+        header.extend(u16::try_from(addresses.len()).unwrap().to_be_bytes());
+        header.extend(addresses);
+        header.extend(b"GET / HTTP/1.1\r\n");
+        header
+    }
+
+    #[tokio::test]
+    async fn reads_v1_headers() {
+        let (addr, rest) = read(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\nGET /").await;
+        assert_eq!(addr.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+
+        let (addr, rest) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\nGET /").await;
+        assert_eq!(addr.unwrap(), Some("[2001:db8::1]:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+
+        let (addr, rest) = read(b"PROXY UNKNOWN ignored stuff\r\nGET /").await;
+        assert_eq!(addr.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_v1_headers() {
+        for header in [
+            &b"GET / HTTP/1.1\r\n\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 56324\r\n",
+            b"PROXY TCP4 2001:db8::1 2001:db8::2 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 65536 443\r\n",
+            b"PROXY UDP4 192.0.2.1 192.0.2.2 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443",
+        ] {
+            let (addr, _) = read(header).await;
+            assert!(addr.is_err(), "{}", String::from_utf8_lossy(header));
+        }
+
+        let too_long = format!("PROXY UNKNOWN {}\r\n", "a".repeat(100));
+        assert!(read(too_long.as_bytes()).await.0.is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_v2_headers() {
+        let ipv4 = [192, 0, 2, 1, 192, 0, 2, 2, 0xdc, 0x04, 0x01, 0xbb];
+        let header = v2_header(0x1, 0x11, &ipv4);
+        let (addr, rest) = read(&header).await;
+        assert_eq!(addr.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+
+        let mut ipv6 = vec![0x20, 0x01, 0x0d, 0xb8];
+        ipv6.extend([0; 11]);
+        ipv6.push(1);
+        ipv6.extend([0; 16]);
+        ipv6.extend([0xdc, 0x04, 0x01, 0xbb]);
+        // Extensions should be skipped
+        ipv6.extend([0x04, 0x00, 0x01, 0x00]);
+        let header = v2_header(0x1, 0x21, &ipv6);
+        let (addr, rest) = read(&header).await;
+        assert_eq!(addr.unwrap(), Some("[2001:db8::1]:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+
+        // The proxy checking on us
+        let header = v2_header(0x0, 0x00, &[]);
+        let (addr, rest) = read(&header).await;
+        assert_eq!(addr.unwrap(), None);
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_v2_headers() {
+        let ipv4 = [192, 0, 2, 1, 192, 0, 2, 2, 0xdc, 0x04, 0x01, 0xbb];
+        let mut wrong_version = v2_header(0x1, 0x11, &ipv4);
+        wrong_version[12] = 0x11;
+        let mut wrong_signature = v2_header(0x1, 0x11, &ipv4);
+        wrong_signature[10] = b'X';
+
+        for header in [
+            wrong_version,
+            wrong_signature,
+            v2_header(0x2, 0x11, &ipv4),
+            v2_header(0x1, 0x11, &ipv4[..8]),
+            v2_header(0x1, 0x21, &ipv4),
+        ] {
+            assert!(read(&header).await.0.is_err(), "{header:?}");
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Reloading the config on SIGHUP, without dropping the connections we are already serving.
+
+use std::sync::{Arc, RwLock};
+
+use axum::{extract::Request, Router};
+use tower::{service_fn, ServiceExt};
+
+/// A router that can be replaced while it is being served.
+///
+/// Requests are handled by the router that was current when they arrived, so streams that have
+/// already started keep their generator when it is replaced.
+#[derive(Debug, Clone)]
+pub(crate) struct ReloadableRouter {
+    current: Arc<RwLock<Router>>,
+}
+
+impl ReloadableRouter {
+    pub fn new(router: Router) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(router)),
+        }
+    }
+
+    /// Handles new requests using `router` instead.
+    pub fn replace(&self, router: Router) {
+        *self.current.write().unwrap() = router;
+    }
+
+    /// A router handling every request using the current one.
+    pub fn router(&self) -> Router {
+        let current = self.current.clone();
+        Router::new().fallback_service(service_fn(move |request: Request| {
+            // Cloning a router is cheap, and lets the lock go before the request is handled
+            let router = current.read().unwrap().clone();
+            router.oneshot(request)
+        }))
+    }
+}
+
+/// SIGHUP, which tells us to reload the config.
+#[derive(Debug)]
+pub(crate) struct Hangups {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangups {
+    /// Starts listening for SIGHUP, which would otherwise stop the process.
+    pub fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let signal = signal(SignalKind::hangup())
+                .inspect_err(|e| tracing::warn!("Cannot listen for SIGHUP: {e}"))
+                .ok();
+            Self { signal }
+        }
+
+        #[cfg(not(unix))]
+        Self {}
+    }
+
+    /// Waits for the next SIGHUP. Never returns on platforms without it.
+    pub async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+
+        std::future::pending().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::ReloadableRouter;
+
+    async fn body(router: Router) -> String {
+        let response = router
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn replaces_router() {
+        let router = ReloadableRouter::new(Router::new().route("/", get(|| async { "old" })));
+        let served = router.router();
+        assert_eq!(body(served.clone()).await, "old");
+
+        // Routers that are already being served use the new one as well
+        router.replace(Router::new().route("/", get(|| async { "new" })));
+        assert_eq!(body(served).await, "new");
+        assert_eq!(body(router.router()).await, "new");
+    }
+}
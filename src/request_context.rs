@@ -0,0 +1,110 @@
+//! Information about an incoming request, handed to generator strategies so they can tailor
+//! their output.
+
+use std::{convert::Infallible, net::IpAddr};
+
+use axum::{
+    extract::FromRequestParts,
+    http::{header::USER_AGENT, request::Parts, HeaderMap, Uri},
+};
+
+use rand::{rngs::SmallRng, SeedableRng};
+
+use crate::client_ip::ClientIp;
+
+/// Describes the request that a stream is generated for.
+///
+/// Can be extracted directly in an axum handler.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RequestContext {
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    /// IP of the client, if known. This takes trusted proxies into account, so it should be
+    /// used for anything that needs to tell clients apart.
+    pub client_ip: Option<IpAddr>,
+    /// Seed for randomly generated data, so that the same output can be generated again. Never
+    /// set for actual requests, as every client should get something new.
+    pub seed: Option<u64>,
+}
+
+impl RequestContext {
+    /// The `User-Agent` header of the request, if set to something readable.
+    pub fn user_agent(&self) -> Option<&str> {
+        self.headers.get(USER_AGENT)?.to_str().ok()
+    }
+
+    /// A random number generator for generating data, using `seed` if set.
+    pub fn rng(&self) -> SmallRng {
+        self.seed
+            .map_or_else(SmallRng::from_os_rng, SmallRng::seed_from_u64)
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for RequestContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            uri: parts.uri.clone(),
+            headers: parts.headers.clone(),
+            client_ip: parts.extensions.get::<ClientIp>().map(|ClientIp(ip)| *ip),
+            seed: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        extract::{ConnectInfo, FromRequestParts},
+        http::{header::USER_AGENT, Request},
+    };
+
+    use crate::{
+        client_ip::TrustedProxies,
+        listener::{ConnectionInfo, PeerAddr},
+    };
+
+    use super::RequestContext;
+
+    async fn context_from<B>(request: Request<B>) -> RequestContext {
+        let (mut parts, _) = request.into_parts();
+        RequestContext::from_request_parts(&mut parts, &())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn extracts_request_details() {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/wp-admin/wp-login.php?user=admin")
+            .header(USER_AGENT, "evil-bot/1.0")
+            .body(())
+            .unwrap();
+        let context = context_from(request).await;
+
+        assert_eq!(context.uri.path(), "/wp-admin/wp-login.php");
+        assert_eq!(context.user_agent(), Some("evil-bot/1.0"));
+        assert_eq!(context.client_ip, None);
+    }
+
+    #[tokio::test]
+    async fn uses_resolved_client_ip() {
+        let mut request = Request::builder()
+            .uri("/")
+            .header("X-Forwarded-For", "198.51.100.1")
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(ConnectInfo(ConnectionInfo {
+            peer: PeerAddr::Tcp("127.0.0.1:41234".parse().unwrap()),
+            proxied: Some("192.0.2.1:56324".parse().unwrap()),
+        }));
+        let trusted_proxies = TrustedProxies::new(&["127.0.0.1/32".parse().unwrap()]);
+        let context = context_from(trusted_proxies.add_client_ip(request)).await;
+
+        // The client told us who it is with the PROXY protocol, so the header is not trusted
+        assert_eq!(context.client_ip, Some("192.0.2.1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,218 @@
+//! Sampling generated data without serving it, to tune generator settings.
+
+use std::io::{self, Write};
+
+use axum::http::Uri;
+use tokio_stream::StreamExt;
+
+use crate::{
+    args::{SampleAmount, SampleArgs},
+    check,
+    config::Config,
+    error_code,
+    request_context::RequestContext,
+    Routes,
+};
+
+/// Writes what a client requesting `args.route` (or any other route, if `None`) would receive
+/// to `output`, prefix included.
+///
+/// Returns an exit code if the config cannot be used, or the data cannot be written.
+pub(crate) async fn sample<W: Write>(
+    config: &Config,
+    args: &SampleArgs,
+    output: &mut W,
+) -> Result<(), i32> {
+    check::report(&check::check(config))?;
+    let routes = Routes::new(config)?;
+
+    let mut context = RequestContext {
+        seed: args.seed,
+        ..RequestContext::default()
+    };
+    let route = match &args.route {
+        Some(path) => {
+            let Some(route) = routes.configured(path) else {
+                eprintln!("there is no route '{path}' in [[routes]]");
+                return Err(error_code::ARGUMENT_ERROR);
+            };
+            // Patterns such as `/api/{*rest}` are no valid paths, but will still do
+            context.uri = path.parse().unwrap_or_else(|_| Uri::from_static("/"));
+            route
+        }
+        None => &routes.default,
+    };
+
+    let generator = route.generator.clone();
+    let permit = generator
+        .acquire_permit()
+        .await
+        .expect("nothing else is generating");
+    let mut stream =
+        Box::pin(generator.into_stream(route.generator_strategy.clone(), context, permit));
+
+    let (SampleAmount::Bytes(mut left) | SampleAmount::Chunks(mut left)) = args.amount;
+    while left > 0 {
+        let Some(mut chunk) = stream.next().await else {
+            break;
+        };
+        if let SampleAmount::Bytes(_) = args.amount {
+            chunk.truncate(left);
+        }
+        match output.write_all(&chunk) {
+            Ok(()) => {}
+            // Such as when piped to `head`
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(e) => {
+                eprintln!("failed to write sample due to error:\n\t{e}");
+                return Err(error_code::UNKNOWN_ERROR);
+            }
+        }
+        left -= match args.amount {
+            SampleAmount::Bytes(_) => chunk.len(),
+            SampleAmount::Chunks(_) => 1,
+        };
+    }
+
+    output.flush().map_err(|e| {
+        eprintln!("failed to write sample due to error:\n\t{e}");
+        error_code::UNKNOWN_ERROR
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use crate::{
+        args::{SampleAmount, SampleArgs},
+        config::{Config, GeneratorType},
+        error_code,
+    };
+
+    use super::sample;
+
+    async fn sample_of(config: &Config, args: &SampleArgs) -> Result<Vec<u8>, i32> {
+        let mut output = Vec::new();
+        sample(config, args, &mut output).await?;
+        Ok(output)
+    }
+
+    #[tokio::test]
+    async fn samples_bytes_and_chunks() {
+        let mut config = Config::default();
+        config.generator.prefix = "<html>".to_string();
+        config.generator.chunk_size = 20;
+
+        let args = SampleArgs {
+            amount: SampleAmount::Bytes(50),
+            ..SampleArgs::default()
+        };
+        let output = sample_of(&config, &args).await.unwrap();
+        assert_eq!(output.len(), 50);
+        assert!(output.starts_with(b"<html><p>\n"));
+
+        // The prefix is part of the first chunk
+        let args = SampleArgs {
+            amount: SampleAmount::Chunks(3),
+            ..SampleArgs::default()
+        };
+        let output = sample_of(&config, &args).await.unwrap();
+        assert_eq!(output.len(), "<html>".len() + 3 * 20);
+    }
+
+    #[tokio::test]
+    async fn seed_gives_same_output() {
+        let config = Config::default();
+        let args = SampleArgs {
+            amount: SampleAmount::Chunks(2),
+            seed: Some(4711),
+            route: None,
+        };
+        let first = sample_of(&config, &args).await.unwrap();
+        assert_eq!(first, sample_of(&config, &args).await.unwrap());
+
+        let args = SampleArgs {
+            seed: Some(4712),
+            ..args
+        };
+        assert_ne!(first, sample_of(&config, &args).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn seed_changes_link_maze() {
+        let mut config = Config::default();
+        config.generator.generator_type = GeneratorType::LinkMaze("/maze".to_string());
+        let args = SampleArgs {
+            seed: Some(4711),
+            ..SampleArgs::default()
+        };
+        let first = sample_of(&config, &args).await.unwrap();
+        assert_eq!(first, sample_of(&config, &args).await.unwrap());
+
+        let args = SampleArgs {
+            seed: Some(4712),
+            ..args
+        };
+        assert_ne!(first, sample_of(&config, &args).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn seed_gives_same_markov_chain_output() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(
+            b"Some text to build a Markov chain from, for sampling. Some more text to build it \
+            from, so that there is more than one way to go. A chain from text.",
+        )
+        .unwrap();
+        let toml_str = format!(
+            r#"
+            [[routes]]
+            path = "/text"
+            type = {{ name = "markov_chain", data = "{}" }}
+            "#,
+            file.path().display()
+        );
+        let config: Config = toml::from_str(&toml_str).unwrap();
+        let args = SampleArgs {
+            amount: SampleAmount::Chunks(3),
+            seed: Some(4711),
+            route: Some("/text".to_string()),
+        };
+        let first = sample_of(&config, &args).await.unwrap();
+        assert_eq!(first, sample_of(&config, &args).await.unwrap());
+
+        let args = SampleArgs {
+            seed: Some(4712),
+            ..args
+        };
+        assert_ne!(first, sample_of(&config, &args).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn samples_routes() {
+        let toml_str = r#"
+            [[routes]]
+            path = "/.env"
+            prefix = "SECRET="
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let args = SampleArgs {
+            route: Some("/.env".to_string()),
+            ..SampleArgs::default()
+        };
+        let output = sample_of(&config, &args).await.unwrap();
+        assert!(output.starts_with(b"SECRET="));
+
+        let args = SampleArgs {
+            route: Some("/wp-login.php".to_string()),
+            ..SampleArgs::default()
+        };
+        assert_eq!(
+            sample_of(&config, &args).await,
+            Err(error_code::ARGUMENT_ERROR)
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! Graceful shutdown on SIGTERM and SIGINT.
+//!
+//! Once signalled, listeners stop accepting connections, and streams get `http.drain_period`
+//! seconds to end on their own before they are closed. A second signal closes them right away.
+
+use std::{io, sync::LazyLock, time::Duration};
+
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use crate::metrics::METRICS;
+
+/// How long closed streams get to send their last chunk before their connections are aborted.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Cancelled when listeners should stop accepting new connections.
+static STOPPING: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
+
+/// Cancelled when streams should be closed.
+static CLOSING: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
+
+/// Cancelled when listeners should stop accepting new connections.
+pub(crate) fn stopping() -> CancellationToken {
+    STOPPING.clone()
+}
+
+/// Cancelled when streams should be closed.
+pub(crate) fn closing() -> CancellationToken {
+    CLOSING.clone()
+}
+
+/// Waits for SIGTERM or SIGINT.
+pub(crate) async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Cannot listen for SIGTERM: {e}");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Stops accepting connections on `servers` and waits for them to finish, closing any streams
+/// still open after `drain_period`.
+pub(crate) async fn drain(mut servers: JoinSet<io::Result<()>>, drain_period: Duration) {
+    tracing::info!(
+        "Shutting down, waiting up to {} s for {} streams to end",
+        drain_period.as_secs(),
+        METRICS.active_streams()
+    );
+    STOPPING.cancel();
+
+    let finished = async {
+        while let Some(result) = servers.join_next().await {
+            match result {
+                Ok(Err(e)) => tracing::warn!("Server failed while shutting down: {e}"),
+                Err(e) => tracing::warn!("Server panicked while shutting down: {e}"),
+                Ok(Ok(())) => {}
+            }
+        }
+    };
+    tokio::pin!(finished);
+
+    tokio::select! {
+        () = &mut finished => return,
+        () = tokio::time::sleep(drain_period) => {}
+        () = signal() => tracing::info!("Signalled again, not waiting for streams to end"),
+    }
+
+    tracing::info!("Closing {} remaining streams", METRICS.active_streams());
+    CLOSING.cancel();
+    if tokio::time::timeout(CLOSE_TIMEOUT, finished).await.is_err() {
+        tracing::warn!("Connections did not close in time, aborting them");
+    }
+}
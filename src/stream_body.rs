@@ -19,9 +19,13 @@ use bytes::Bytes;
 use futures::{stream::BoxStream, Stream, StreamExt};
 use http_body::Frame;
 
+use crate::client_limits::ClientPermit;
+
 pub struct StreamBody<'a> {
     stream: BoxStream<'a, Result<Frame<axum::body::Bytes>, axum::Error>>,
     trailers: Option<HeaderMap>,
+    /// Released when the body is dropped, which is when the stream ends.
+    permit: ClientPermit,
 }
 
 impl<'a> StreamBody<'a> {
@@ -29,6 +33,7 @@ impl<'a> StreamBody<'a> {
         Self {
             stream: Box::pin(stream.map(axum::body::Bytes::from).map(Frame::data).map(Ok)),
             trailers: None,
+            permit: ClientPermit::default(),
         }
     }
 }
@@ -39,6 +44,12 @@ impl StreamBody<'_> {
         self.trailers = Some(headers);
         self
     }
+
+    /// Hold on to a client permit for as long as the body is being sent.
+    pub fn permit(mut self, permit: ClientPermit) -> Self {
+        self.permit = permit;
+        self
+    }
 }
 
 impl http_body::Body for StreamBody<'_> {
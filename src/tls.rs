@@ -0,0 +1,291 @@
+//! Built-in TLS support using rustls, for running without a reverse proxy in front.
+
+use std::{
+    fs, io,
+    path::Path,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{
+    rustls::{
+        crypto::{ring, CryptoProvider},
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+
+use crate::{
+    config::TlsConfig,
+    error_code,
+    listener::{ConnectionInfo, Handshake},
+};
+
+/// Creates an acceptor from a config, that will keep reloading certificates as they change.
+///
+/// Returns an exit code if the certificate cannot be loaded.
+pub(crate) fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor, i32> {
+    let provider = Arc::new(ring::default_provider());
+    let resolver = CertificateResolver::new(config, provider.clone()).map_err(|e| {
+        eprintln!("{e}");
+        error_code::CANNOT_LOAD_TLS_CERTIFICATE
+    })?;
+    let resolver = Arc::new(resolver);
+
+    let mut server_config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| {
+            eprintln!("cannot use TLS due to error: {e}");
+            error_code::UNKNOWN_ERROR
+        })?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    if config.reload_interval != 0 {
+        let interval = Duration::from_secs(config.reload_interval);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                match resolver.reload_if_changed() {
+                    Ok(true) => tracing::info!("Reloaded TLS certificate"),
+                    Ok(false) => {}
+                    Err(e) => tracing::error!("Keeping old TLS certificate: {e}"),
+                }
+            }
+        });
+    }
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Loads the certificate and key of a config, without serving them.
+pub(crate) fn check(config: &TlsConfig) -> Result<(), String> {
+    CertificateResolver::new(config, Arc::new(ring::default_provider())).map(drop)
+}
+
+/// Provides the certificate for all connections, and can reload it from disk.
+#[derive(Debug)]
+struct CertificateResolver {
+    config: TlsConfig,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<LoadedCertificate>,
+}
+
+#[derive(Debug)]
+struct LoadedCertificate {
+    key: Arc<CertifiedKey>,
+    /// Latest modification time of the certificate and key files when loaded.
+    modified: Option<SystemTime>,
+}
+
+impl CertificateResolver {
+    fn new(config: &TlsConfig, provider: Arc<CryptoProvider>) -> Result<Self, String> {
+        let current = Self::load(config, &provider)?;
+        Ok(Self {
+            config: config.clone(),
+            provider,
+            current: RwLock::new(current),
+        })
+    }
+
+    fn load(config: &TlsConfig, provider: &CryptoProvider) -> Result<LoadedCertificate, String> {
+        // Read this first, so a change while we are loading results in another reload later
+        let modified = last_modified(config);
+
+        let certs = CertificateDer::pem_file_iter(&config.cert_path)
+            .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+            .map_err(|e| {
+                format!(
+                    "cannot read TLS certificates from '{}' due to error: {e}",
+                    config.cert_path.to_string_lossy()
+                )
+            })?;
+        if certs.is_empty() {
+            return Err(format!(
+                "no TLS certificates found in '{}'",
+                config.cert_path.to_string_lossy()
+            ));
+        }
+
+        let key = PrivateKeyDer::from_pem_file(&config.key_path).map_err(|e| {
+            format!(
+                "cannot read TLS private key from '{}' due to error: {e}",
+                config.key_path.to_string_lossy()
+            )
+        })?;
+
+        let key = CertifiedKey::from_der(certs, key, provider)
+            .map_err(|e| format!("cannot use TLS certificate and key due to error: {e}"))?;
+        Ok(LoadedCertificate {
+            key: Arc::new(key),
+            modified,
+        })
+    }
+
+    /// Reloads the certificate if the files have been modified since last load. Returns `true` if
+    /// the certificate was reloaded.
+    fn reload_if_changed(&self) -> Result<bool, String> {
+        let modified = last_modified(&self.config);
+        if modified == self.current.read().expect("lock poisoned").modified {
+            return Ok(false);
+        }
+
+        let reloaded = Self::load(&self.config, &self.provider)?;
+        *self.current.write().expect("lock poisoned") = reloaded;
+        Ok(true)
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().expect("lock poisoned").key.clone())
+    }
+}
+
+/// Latest modification time of the certificate and key files, if it can be read.
+fn last_modified(config: &TlsConfig) -> Option<SystemTime> {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    modified(&config.cert_path).max(modified(&config.key_path))
+}
+
+impl<Io> Handshake<Io> for TlsAcceptor
+where
+    Io: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Io = TlsStream<Io>;
+
+    const NAME: &'static str = "TLS handshake";
+
+    async fn handshake(&self, io: Io, _info: &mut ConnectionInfo) -> io::Result<Self::Io> {
+        self.accept(io).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc, time::Duration};
+
+    use axum::{routing::get, Router};
+    use tempfile::NamedTempFile;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_rustls::{
+        rustls::{crypto::ring, pki_types::ServerName, ClientConfig, RootCertStore},
+        TlsConnector,
+    };
+
+    use crate::{config::TlsConfig, listener::HandshakeListener};
+
+    use super::{acceptor, CertificateResolver};
+
+    /// Writes a new self-signed certificate and its key to files, returning the certificate.
+    fn write_self_signed(cert_file: &Path, key_file: &Path) -> rcgen::Certificate {
+        let rcgen::CertifiedKey { cert, signing_key } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(cert_file, cert.pem()).unwrap();
+        std::fs::write(key_file, signing_key.serialize_pem()).unwrap();
+        cert
+    }
+
+    fn tls_config(cert_file: &NamedTempFile, key_file: &NamedTempFile) -> TlsConfig {
+        TlsConfig {
+            cert_path: cert_file.path().to_path_buf(),
+            key_path: key_file.path().to_path_buf(),
+            reload_interval: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_https_with_alpn() {
+        let (cert_file, key_file) = (NamedTempFile::new().unwrap(), NamedTempFile::new().unwrap());
+        let cert = write_self_signed(cert_file.path(), key_file.path());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = acceptor(&tls_config(&cert_file, &key_file)).unwrap();
+        let app = Router::new().route("/", get(|| async { "secret" }));
+        tokio::spawn(async move {
+            axum::serve(HandshakeListener::new(listener, acceptor).unwrap(), app)
+                .await
+                .unwrap();
+        });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.der().clone()).unwrap();
+        let mut client_config =
+            ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        client_config.alpn_protocols = vec![b"h2".to_vec()];
+        let h2_connector = TlsConnector::from(Arc::new(client_config.clone()));
+        client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let connector = TlsConnector::from(Arc::new(client_config));
+
+        // A client that never completes its handshake should not block others
+        let _idle = TcpStream::connect(addr).await.unwrap();
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let stream = h2_connector
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = connector
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("secret"), "{response}");
+    }
+
+    #[test]
+    fn reloads_changed_certificate() {
+        let (cert_file, key_file) = (NamedTempFile::new().unwrap(), NamedTempFile::new().unwrap());
+        write_self_signed(cert_file.path(), key_file.path());
+        let resolver = CertificateResolver::new(
+            &tls_config(&cert_file, &key_file),
+            Arc::new(ring::default_provider()),
+        )
+        .unwrap();
+        let first = resolver.current.read().unwrap().key.clone();
+        assert!(!resolver.reload_if_changed().unwrap(), "nothing changed");
+
+        // Make sure the modification time is different, file systems may be coarse
+        std::thread::sleep(Duration::from_millis(20));
+        write_self_signed(cert_file.path(), key_file.path());
+        assert!(resolver.reload_if_changed().unwrap(), "did not reload");
+        assert_ne!(first.cert, resolver.current.read().unwrap().key.cert);
+
+        // Broken files keep the old certificate around
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(cert_file.path(), "").unwrap();
+        std::fs::write(key_file.path(), "garbage").unwrap();
+        let working = resolver.current.read().unwrap().key.clone();
+        assert!(resolver.reload_if_changed().is_err());
+        assert!(Arc::ptr_eq(&working, &resolver.current.read().unwrap().key));
+    }
+}